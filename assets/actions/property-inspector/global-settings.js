@@ -0,0 +1,116 @@
+/**
+ * The fields every property inspector shares: the global settings of the plugin
+ * and the hold and double tap events of a key.
+ *
+ * Load it after the Stream Deck libs. It fills <form id="property-inspector-global">
+ * and replaces <div id="gesture-settings"> in the local form right away, so the gesture
+ * fields are there when the inspector sets the form values of the key on connect.
+ */
+
+const GLOBAL_SETTINGS_HTML = `
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="TargetIp">Target IP</div>
+        <input id="TargetIp" data-localize class="sdpi-item-value" name="TargetIp" type="text" value="" placeholder="127.0.0.1" />
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="TargetPort">Target Port</div>
+        <input id="TargetPort" data-localize class="sdpi-item-value" name="TargetPort" type="text" value="37337"/>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="IconTheme">Icon Theme</div>
+        <select class="sdpi-item-value select" id="IconTheme" name="IconTheme">
+            <option value="classic">Classic</option>
+            <option value="high-contrast">High Contrast</option>
+            <option value="minimal">Minimal</option>
+            <option value="custom">Custom Folder</option>
+        </select>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="IconFolder">Icon Folder</div>
+        <input id="IconFolder" data-localize class="sdpi-item-value" name="IconFolder" type="text" value="" placeholder="C:\\Icons"/>
+    </div>
+    <div type="checkbox" class="sdpi-item">
+        <div class="sdpi-item-label">Interlocks</div>
+        <div class="sdpi-item-value">
+            <span class="sdpi-item-child">
+                <input id="InterlockDoors" type="checkbox" name="InterlockDoors" value="True">
+                <label for="InterlockDoors"><span></span>Doors only when standing</label>
+            </span>
+            <span class="sdpi-item-child">
+                <input id="InterlockDoorBrake" type="checkbox" name="InterlockDoorBrake" value="True">
+                <label for="InterlockDoorBrake"><span></span>Doors only with brake set</label>
+            </span>
+            <span class="sdpi-item-child">
+                <input id="InterlockGears" type="checkbox" name="InterlockGears" value="True">
+                <label for="InterlockGears"><span></span>Only N while rolling</label>
+            </span>
+        </div>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="InterlockSpeed">Standing below km/h</div>
+        <input id="InterlockSpeed" data-localize class="sdpi-item-value" name="InterlockSpeed" type="text" value="" placeholder="3"/>
+    </div>
+    <div type="checkbox" class="sdpi-item">
+        <div class="sdpi-item-label">Profiles</div>
+        <input class="sdpi-item-value" id="SwitchProfiles" name="SwitchProfiles" type="checkbox" value="True">
+        <label for="SwitchProfiles"><span></span>Switch when entering a bus</label>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="BusProfile">Bus Profile</div>
+        <input id="BusProfile" data-localize class="sdpi-item-value" name="BusProfile" type="text" value="" placeholder="profiles/LionsCity"/>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="DepotProfile">Depot Profile</div>
+        <input id="DepotProfile" data-localize class="sdpi-item-value" name="DepotProfile" type="text" value="" placeholder="profiles/Depot"/>
+    </div>
+`;
+
+const GESTURE_SETTINGS_HTML = `
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="HoldEvent">Hold Event</div>
+        <input id="HoldEvent" data-localize class="sdpi-item-value" name="HoldEvent" type="text" value="" placeholder="ToggleDoorClearance"/>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="HoldTime">Hold Time (ms)</div>
+        <input id="HoldTime" data-localize class="sdpi-item-value" name="HoldTime" type="text" value="" placeholder="500"/>
+    </div>
+    <div class="sdpi-item">
+        <div data-localize class="sdpi-item-label" title="DoubleTapEvent">Double Tap Event</div>
+        <input id="DoubleTapEvent" data-localize class="sdpi-item-value" name="DoubleTapEvent" type="text" value="" placeholder=""/>
+    </div>
+`;
+
+document.querySelector('#property-inspector-global').innerHTML = GLOBAL_SETTINGS_HTML;
+
+const gestureSettings = document.querySelector('#gesture-settings');
+if (gestureSettings) {
+    gestureSettings.outerHTML = GESTURE_SETTINGS_HTML;
+}
+
+$PI.onConnected(() => {
+    const formGlobal = document.querySelector('#property-inspector-global');
+
+    $PI.getGlobalSettings();
+
+    formGlobal.addEventListener('input', Utils.debounce(150, () => {
+        const value = Utils.getFormValue(formGlobal);
+        console.log('SetGlobalSettings', value);
+        $PI.setGlobalSettings(value);
+    }));
+});
+
+$PI.onDidReceiveGlobalSettings(({payload}) => {
+    console.log('onDidReceiveGlobalSettings', payload);
+    const settings = payload.settings;
+    document.getElementById("TargetIp").value = settings.TargetIp || "";
+    document.getElementById("TargetPort").value = settings.TargetPort || "";
+    document.getElementById("IconTheme").value = settings.IconTheme || "classic";
+    document.getElementById("IconFolder").value = settings.IconFolder || "";
+    document.getElementById("InterlockDoors").checked = settings.InterlockDoors == "True";
+    document.getElementById("InterlockDoorBrake").checked = settings.InterlockDoorBrake == "True";
+    document.getElementById("InterlockGears").checked = settings.InterlockGears == "True";
+    document.getElementById("InterlockSpeed").value = settings.InterlockSpeed || "";
+    document.getElementById("SwitchProfiles").checked = settings.SwitchProfiles == "True";
+    document.getElementById("BusProfile").value = settings.BusProfile || "";
+    document.getElementById("DepotProfile").value = settings.DepotProfile || "";
+});
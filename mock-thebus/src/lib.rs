@@ -0,0 +1,314 @@
+//! A small stand-in for the telemetry HTTP API of The Bus.
+//!
+//! It serves scripted vehicle JSON, records every command it receives
+//! and can pretend that the game has been closed. This way the plugin can be
+//! developed and tested without the game (or Windows) at hand.
+//!
+//! `streamdeck::FakeStreamDeck` does the same for the Stream Deck application.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+pub mod streamdeck;
+
+/// The port the game listens on by default.
+pub const DEFAULT_PORT: u16 = 37337;
+
+#[derive(Default)]
+struct MockState {
+    // None: the player is not sitting in a bus
+    vehicle: Option<serde_json::Value>,
+    // None: no ticket is being sold
+    payment: Option<serde_json::Value>,
+    commands: Vec<String>,
+    requests: Vec<String>,
+}
+
+pub struct MockTheBus {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    // None while the game is closed, nothing listens on the port then
+    server: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl MockTheBus {
+    /// Starts the mock on a random free port on localhost.
+    pub async fn start() -> io::Result<Self> {
+        Self::start_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn start_on(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let server = serve(listener, state.clone());
+
+        Ok(MockTheBus {
+            addr,
+            state,
+            server: Mutex::new(Some(server)),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Puts the player into the driver's seat of the given vehicle.
+    /// The JSON is served as-is for `/vehicles/Current` and `/vehicles/<ActorName>`.
+    pub fn set_vehicle(&self, vehicle: serde_json::Value) {
+        self.state.lock().unwrap().vehicle = Some(vehicle);
+    }
+
+    /// The player leaves the bus (game keeps running).
+    pub fn leave_vehicle(&self) {
+        self.state.lock().unwrap().vehicle = None;
+    }
+
+    /// Starts a ticket sale, served for `/vehicles/<name>/ticketsale`
+    /// (e.g. `{"TicketName": "Single", "PaymentMethod": "Cash", "Price": 2.5, "Paid": 5.0, "Change": 2.5}`).
+    /// This is the shape the plugin assumes, the game's answer has not been checked.
+    pub fn set_payment(&self, payment: serde_json::Value) {
+        self.state.lock().unwrap().payment = Some(payment);
+    }
+
+    pub fn clear_payment(&self) {
+        self.state.lock().unwrap().payment = None;
+    }
+
+    /// Simulates the game being closed: the port is released, so connecting is refused.
+    pub async fn close_game(&self) {
+        let server = self.server.lock().unwrap().take();
+        if let Some(server) = server {
+            server.abort();
+            // the listener is dropped once the aborted task is done
+            let _ = server.await;
+        }
+    }
+
+    /// Listens on the same port again after `close_game`.
+    pub async fn open_game(&self) -> io::Result<()> {
+        if self.server.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        let listener = TcpListener::bind(self.addr).await?;
+        *self.server.lock().unwrap() = Some(serve(listener, self.state.clone()));
+        Ok(())
+    }
+
+    /// All commands received so far, as the plugin sends them (e.g. `sendeventpress?event=SetGearD`).
+    pub fn commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Like `commands`, but clears the list.
+    pub fn take_commands(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().commands)
+    }
+
+    /// Waits until at least `count` commands have been received (or `timeout` is over)
+    /// and returns them, clearing the list.
+    pub async fn wait_for_commands(&self, count: usize, timeout: Duration) -> Vec<String> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.commands().len() < count && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        self.take_commands()
+    }
+
+    /// Every request path (including the data requests) received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockTheBus {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.lock().unwrap().take() {
+            server.abort();
+        }
+    }
+}
+
+fn serve(listener: TcpListener, state: Arc<Mutex<MockState>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_connection(stream, state.clone()));
+        }
+    })
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    // we only need the request line, GET requests have no body
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buf);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/")
+        .to_string();
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(path.clone());
+        respond(&mut state, &path)
+    };
+
+    let (status, body) = match response {
+        Some(body) => ("200 OK", body),
+        None => ("404 Not Found", "{}".to_string()),
+    };
+    let answer = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(answer.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn respond(state: &mut MockState, path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    match segments.as_slice() {
+        ["vehicles"] => {
+            let names = match &state.vehicle {
+                Some(vehicle) => vec![actor_name(vehicle)],
+                None => vec![],
+            };
+            Some(serde_json::json!(names).to_string())
+        }
+        ["player"] => {
+            let (mode, vehicle) = match &state.vehicle {
+                Some(vehicle) => ("Vehicle", actor_name(vehicle)),
+                None => ("Walking", "".to_string()),
+            };
+            Some(serde_json::json!({ "Mode": mode, "CurrentVehicle": vehicle }).to_string())
+        }
+        ["vehicles", _, "ticketsale"] => {
+            state.vehicle.as_ref()?;
+            state.payment.as_ref().map(|payment| payment.to_string())
+        }
+        ["vehicles", name] if !name.contains('?') => {
+            let vehicle = state.vehicle.as_ref()?;
+            if *name == "Current" || *name == actor_name(vehicle) {
+                Some(vehicle.to_string())
+            } else {
+                None
+            }
+        }
+        _ => {
+            // everything else is a command, recorded the way the plugin writes it
+            let command = segments.last().copied().unwrap_or_default().to_string();
+            state.commands.push(command);
+            Some("{\"Result\":\"Ok\"}".to_string())
+        }
+    }
+}
+
+fn actor_name(vehicle: &serde_json::Value) -> String {
+    vehicle
+        .get("ActorName")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Current")
+        .to_string()
+}
+
+/// A MAN Lion's City with the engine running, standing at a bus stop.
+pub fn sample_vehicle() -> serde_json::Value {
+    serde_json::from_str(include_str!("../vehicles/man_lions_city.json"))
+        .expect("sample vehicle is valid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(port: u16, path: &str) -> io::Result<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await?;
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).await?;
+        Ok(answer)
+    }
+
+    #[tokio::test]
+    async fn test_serves_vehicle() {
+        let mock = MockTheBus::start().await.unwrap();
+
+        let answer = get(mock.port(), "/vehicles/Current").await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 404"));
+
+        mock.set_vehicle(sample_vehicle());
+        let answer = get(mock.port(), "/vehicles/Current").await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200"));
+        assert!(answer.contains("VehicleModel"));
+    }
+
+    #[tokio::test]
+    async fn test_records_commands() {
+        let mock = MockTheBus::start().await.unwrap();
+        mock.set_vehicle(sample_vehicle());
+
+        get(mock.port(), "/vehicles/Current/sendeventpress?event=SetGearD").await.unwrap();
+        get(mock.port(), "/vehicles/Current/sendeventrelease?event=SetGearD").await.unwrap();
+
+        assert_eq!(
+            mock.wait_for_commands(2, Duration::from_secs(1)).await,
+            vec!["sendeventpress?event=SetGearD", "sendeventrelease?event=SetGearD"]
+        );
+        assert!(mock.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_serves_payment() {
+        let mock = MockTheBus::start().await.unwrap();
+        mock.set_vehicle(sample_vehicle());
+
+        let answer = get(mock.port(), "/vehicles/Current/ticketsale").await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 404"));
+
+        mock.set_payment(serde_json::json!({ "Price": 2.5 }));
+        let answer = get(mock.port(), "/vehicles/Current/ticketsale").await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200"));
+        assert!(answer.contains("2.5"));
+        assert!(mock.commands().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_closed_game() {
+        let mock = MockTheBus::start().await.unwrap();
+        mock.set_vehicle(sample_vehicle());
+        mock.close_game().await;
+
+        // nothing listens anymore, as with the game closed
+        assert!(TcpStream::connect(mock.addr()).await.is_err());
+
+        mock.open_game().await.unwrap();
+        let answer = get(mock.port(), "/vehicles/Current").await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 200"));
+    }
+}
@@ -0,0 +1,96 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use thebus_mock::{sample_vehicle, MockTheBus, DEFAULT_PORT};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const USAGE: &str = "usage: thebus-mock [--port <port>] [vehicle.json]
+
+commands (on stdin):
+  enter         sit down in the vehicle
+  leave         leave the vehicle
+  close         pretend the game has been closed
+  open          pretend the game is running again
+  load <file>   serve another vehicle JSON file";
+
+fn load_vehicle(path: &str) -> Result<serde_json::Value, String> {
+    let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_str(&data).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[tokio::main(worker_threads = 1)]
+async fn main() {
+    let mut port = DEFAULT_PORT;
+    let mut vehicle = sample_vehicle();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => match args.next().and_then(|p| p.parse().ok()) {
+                Some(p) => port = p,
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            path => match load_vehicle(path) {
+                Ok(v) => vehicle = v,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            },
+        }
+    }
+
+    let mock = match MockTheBus::start_on(SocketAddr::from(([0, 0, 0, 0], port))).await {
+        Ok(mock) => mock,
+        Err(e) => {
+            eprintln!("Failed to listen on port {}: {}", port, e);
+            return;
+        }
+    };
+    mock.set_vehicle(vehicle.clone());
+    println!("The Bus mock listening on {}", mock.addr());
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else { break };
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("enter"), _) => mock.set_vehicle(vehicle.clone()),
+                    (Some("leave"), _) => mock.leave_vehicle(),
+                    (Some("close"), _) => mock.close_game().await,
+                    (Some("open"), _) => {
+                        if let Err(e) = mock.open_game().await {
+                            eprintln!("Failed to listen on port {}: {}", port, e);
+                        }
+                    }
+                    (Some("load"), Some(path)) => match load_vehicle(path) {
+                        Ok(v) => {
+                            vehicle = v;
+                            mock.set_vehicle(vehicle.clone());
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    },
+                    (None, _) => {}
+                    _ => println!("{}", USAGE),
+                }
+            },
+
+            _ = ticker.tick() => {
+                for command in mock.take_commands() {
+                    println!("> {}", command);
+                }
+            }
+        }
+    }
+}
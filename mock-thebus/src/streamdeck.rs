@@ -0,0 +1,342 @@
+//! A fake Stream Deck application speaking the SDK v2 websocket protocol.
+//!
+//! It hands out the command line the Stream Deck software would start the plugin with,
+//! accepts the plugin's connection and lets a test send events and inspect the
+//! messages (`setState`, `setImage`, `setTitle`, ...) the plugin sends back.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+pub const PLUGIN_UUID: &str = "de.thatzok.thebus";
+pub const REGISTER_EVENT: &str = "registerPlugin";
+pub const DEVICE: &str = "FAKEDEVICE";
+
+pub struct FakeStreamDeck {
+    listener: TcpListener,
+    socket: Option<WebSocketStream<TcpStream>>,
+    // messages from the plugin that have not been looked at yet
+    received: Vec<Value>,
+}
+
+impl FakeStreamDeck {
+    /// Starts listening on a random free port on localhost.
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        Ok(FakeStreamDeck {
+            listener,
+            socket: None,
+            received: Vec::new(),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+
+    /// The arguments the Stream Deck software passes to a plugin on startup.
+    pub fn plugin_args(&self) -> Vec<String> {
+        let info = json!({
+            "application": {
+                "font": "ui-sans",
+                "language": "en",
+                "platform": "windows",
+                "platformVersion": "10.0.19045",
+                "version": "6.6.0.19000"
+            },
+            "plugin": { "uuid": PLUGIN_UUID, "version": "1.5.0" },
+            "devicePixelRatio": 1,
+            "colors": {
+                "buttonPressedBackgroundColor": "#303030FF",
+                "buttonPressedBorderColor": "#646464FF",
+                "buttonPressedTextColor": "#969696FF",
+                "disabledColor": "#F7821B59",
+                "highlightColor": "#F7821BFF",
+                "mouseDownColor": "#CF6304FF"
+            },
+            "devices": [{
+                "id": DEVICE,
+                "name": "Stream Deck",
+                "size": { "columns": 5, "rows": 3 },
+                "type": 0
+            }]
+        });
+
+        vec![
+            "-port".to_string(),
+            self.port().to_string(),
+            "-pluginUUID".to_string(),
+            PLUGIN_UUID.to_string(),
+            "-registerEvent".to_string(),
+            REGISTER_EVENT.to_string(),
+            "-info".to_string(),
+            info.to_string(),
+        ]
+    }
+
+    /// Waits for the plugin to connect and register itself.
+    pub async fn accept(&mut self, timeout: Duration) -> io::Result<()> {
+        let (stream, _) = tokio::time::timeout(timeout, self.listener.accept())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "plugin did not connect"))??;
+        let socket = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.socket = Some(socket);
+
+        let registration = self
+            .next_message(timeout)
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "plugin did not register"))?;
+        if registration["event"] != REGISTER_EVENT || registration["uuid"] != PLUGIN_UUID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected registration {}", registration),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn send(&mut self, event: Value) {
+        if let Some(socket) = self.socket.as_mut() {
+            let _ = socket.send(Message::Text(event.to_string())).await;
+        }
+    }
+
+    pub async fn will_appear(&mut self, action: &str, context: &str, settings: Value) {
+        self.appear(action, context, settings, "Keypad").await;
+    }
+
+    /// Like `will_appear`, for a dial of a Stream Deck+.
+    pub async fn will_appear_dial(&mut self, action: &str, context: &str, settings: Value) {
+        self.appear(action, context, settings, "Encoder").await;
+    }
+
+    async fn appear(&mut self, action: &str, context: &str, settings: Value, controller: &str) {
+        self.send(json!({
+            "action": action,
+            "event": "willAppear",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": settings,
+                "coordinates": { "column": 0, "row": 0 },
+                "controller": controller,
+                "state": 0,
+                "isInMultiAction": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn will_disappear(&mut self, action: &str, context: &str) {
+        self.send(json!({
+            "action": action,
+            "event": "willDisappear",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": {},
+                "coordinates": { "column": 0, "row": 0 },
+                "controller": "Keypad",
+                "state": 0,
+                "isInMultiAction": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn key_down(&mut self, action: &str, context: &str) {
+        self.key_event("keyDown", action, context).await;
+    }
+
+    pub async fn key_up(&mut self, action: &str, context: &str) {
+        self.key_event("keyUp", action, context).await;
+    }
+
+    async fn key_event(&mut self, event: &str, action: &str, context: &str) {
+        self.send(json!({
+            "action": action,
+            "event": event,
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": {},
+                "coordinates": { "column": 0, "row": 0 },
+                "state": 0,
+                "isInMultiAction": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn dial_rotate(&mut self, action: &str, context: &str, ticks: i64) {
+        self.send(json!({
+            "action": action,
+            "event": "dialRotate",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": {},
+                "coordinates": { "column": 0, "row": 0 },
+                "ticks": ticks,
+                "pressed": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn dial_press(&mut self, action: &str, context: &str, pressed: bool) {
+        self.send(json!({
+            "action": action,
+            "event": "dialPress",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": {},
+                "coordinates": { "column": 0, "row": 0 },
+                "pressed": pressed
+            }
+        }))
+        .await;
+    }
+
+    pub async fn touch_tap(&mut self, action: &str, context: &str) {
+        self.send(json!({
+            "action": action,
+            "event": "touchTap",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": {},
+                "coordinates": { "column": 0, "row": 0 },
+                "tapPos": [50, 50],
+                "hold": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn did_receive_settings(&mut self, action: &str, context: &str, settings: Value) {
+        self.send(json!({
+            "action": action,
+            "event": "didReceiveSettings",
+            "context": context,
+            "device": DEVICE,
+            "payload": {
+                "settings": settings,
+                "coordinates": { "column": 0, "row": 0 },
+                "isInMultiAction": false
+            }
+        }))
+        .await;
+    }
+
+    pub async fn did_receive_global_settings(&mut self, settings: Value) {
+        self.send(json!({
+            "event": "didReceiveGlobalSettings",
+            "payload": { "settings": settings }
+        }))
+        .await;
+    }
+
+    /// Next message from the plugin, None if nothing arrives within `timeout`.
+    pub async fn next_message(&mut self, timeout: Duration) -> Option<Value> {
+        if !self.received.is_empty() {
+            return Some(self.received.remove(0));
+        }
+        self.read_message(timeout).await
+    }
+
+    async fn read_message(&mut self, timeout: Duration) -> Option<Value> {
+        let socket = self.socket.as_mut()?;
+        loop {
+            let message = tokio::time::timeout(timeout, socket.next()).await.ok()??.ok()?;
+            if let Ok(value) = serde_json::from_str(message.to_text().unwrap_or_default()) {
+                return Some(value);
+            }
+        }
+    }
+
+    /// Waits for a message with the given event for the given context.
+    /// Other messages are kept for later calls.
+    pub async fn expect(&mut self, event: &str, context: &str, timeout: Duration) -> Option<Value> {
+        let matches = |m: &Value| m["event"] == event && m["context"] == context;
+
+        if let Some(pos) = self.received.iter().position(matches) {
+            return Some(self.received.remove(pos));
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let message = self.read_message(left).await?;
+            if matches(&message) {
+                return Some(message);
+            }
+            self.received.push(message);
+        }
+    }
+
+    /// All messages received so far that have not been looked at yet.
+    pub async fn drain(&mut self, timeout: Duration) -> Vec<Value> {
+        while let Some(message) = self.read_message(timeout).await {
+            self.received.push(message);
+        }
+        std::mem::take(&mut self.received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::connect_async;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn test_register_and_exchange() {
+        let mut deck = FakeStreamDeck::start().await.unwrap();
+        let url = format!("ws://127.0.0.1:{}", deck.port());
+
+        let plugin = tokio::spawn(async move {
+            let (mut socket, _) = connect_async(url).await.unwrap();
+            let register = json!({ "event": REGISTER_EVENT, "uuid": PLUGIN_UUID });
+            socket.send(Message::Text(register.to_string())).await.unwrap();
+
+            // answer the first event with a setState
+            let event = socket.next().await.unwrap().unwrap();
+            let event: Value = serde_json::from_str(event.to_text().unwrap()).unwrap();
+            let answer = json!({ "event": "setState", "context": event["context"], "payload": { "state": 1 } });
+            socket.send(Message::Text(answer.to_string())).await.unwrap();
+            socket
+        });
+
+        deck.accept(TIMEOUT).await.unwrap();
+        deck.key_down("de.thatzok.thebus.inbus", "ctx1").await;
+
+        let answer = deck.expect("setState", "ctx1", TIMEOUT).await.unwrap();
+        assert_eq!(answer["payload"]["state"], 1);
+
+        drop(plugin.await.unwrap());
+    }
+
+    #[test]
+    fn test_plugin_args() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let deck = runtime.block_on(FakeStreamDeck::start()).unwrap();
+        let args = deck.plugin_args();
+
+        assert_eq!(args[0], "-port");
+        assert_eq!(args[1], deck.port().to_string());
+        assert_eq!(args[3], PLUGIN_UUID);
+        assert_eq!(args[5], REGISTER_EVENT);
+        assert!(serde_json::from_str::<Value>(&args[7]).is_ok());
+    }
+}
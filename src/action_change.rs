@@ -1,28 +1,33 @@
+use std::any::Any;
 use std::collections::HashMap;
 
-// Access items from the parent module (main.rs)
-use super::{get_value_or_empty, ActionInstance};
+use async_trait::async_trait;
 use rusty_patio::streamdeck::generic::StreamDeckTarget;
-use rusty_patio::streamdeck::{client::StreamDeckClient, events::event_received::EventReceived};
-use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext};
+use super::settings::{parse_boxed_settings, ChangeSettings, Coin};
+use super::ActionInstance;
+
+pub const UUID: &str = "de.thatzok.thebus.changeaction";
 
-pub fn get_coin_title(coin: &str) -> String {
+pub fn get_coin_title(coin: Coin) -> String {
     let mut d = "";
     d = match coin {
-        "Coins5" => "0.05 €",
-        "Coins10" => "0.10 €",
-        "Coins15" => "0.15 €",
-        "Coins20" => "0.20 €",
-        "Coins30" => "0.30 €",
-        "Coins50" => "0.50 €",
-        "Coins60" => "0.60 €",
-        "Coins100" => "1.00 €",
-        "Coins200" => "2.00 €",
-        "Coins400" => "4.00 €",
-        "Coins600" => "6.00 €",
-        "Coins800" => "8.00 €",
-        "Take Cash Money" => "Grab",
-        _ => "Grab",
+        Coin::Coins5 => "0.05 €",
+        Coin::Coins10 => "0.10 €",
+        Coin::Coins15 => "0.15 €",
+        Coin::Coins20 => "0.20 €",
+        Coin::Coins30 => "0.30 €",
+        Coin::Coins50 => "0.50 €",
+        Coin::Coins60 => "0.60 €",
+        Coin::Coins100 => "1.00 €",
+        Coin::Coins200 => "2.00 €",
+        Coin::Coins400 => "4.00 €",
+        Coin::Coins600 => "6.00 €",
+        Coin::Coins800 => "8.00 €",
+        Coin::TakeCashMoney => "Grab",
     };
     d.to_string()
 }
@@ -33,94 +38,57 @@ mod tests {
 
     #[test]
     fn test_get_coin_title() {
-        assert_eq!(get_coin_title("Coins5"), "0.05 €");
-        assert_eq!(get_coin_title("Coins10"), "0.10 €");
-        assert_eq!(get_coin_title("Coins15"), "0.15 €");
-        assert_eq!(get_coin_title("Coins20"), "0.20 €");
-        assert_eq!(get_coin_title("Coins30"), "0.30 €");
-        assert_eq!(get_coin_title("Coins50"), "0.50 €");
-        assert_eq!(get_coin_title("Coins60"), "0.60 €");
-        assert_eq!(get_coin_title("Coins100"), "1.00 €");
-        assert_eq!(get_coin_title("Coins200"), "2.00 €");
-        assert_eq!(get_coin_title("Coins400"), "4.00 €");
-        assert_eq!(get_coin_title("Coins600"), "6.00 €");
-        assert_eq!(get_coin_title("Coins800"), "8.00 €");
-        assert_eq!(get_coin_title("Take Cash Money"), "Grab");
-        assert_eq!(get_coin_title("Unknown"), "Grab");
+        assert_eq!(get_coin_title(Coin::Coins5), "0.05 €");
+        assert_eq!(get_coin_title(Coin::Coins10), "0.10 €");
+        assert_eq!(get_coin_title(Coin::Coins15), "0.15 €");
+        assert_eq!(get_coin_title(Coin::Coins20), "0.20 €");
+        assert_eq!(get_coin_title(Coin::Coins30), "0.30 €");
+        assert_eq!(get_coin_title(Coin::Coins50), "0.50 €");
+        assert_eq!(get_coin_title(Coin::Coins60), "0.60 €");
+        assert_eq!(get_coin_title(Coin::Coins100), "1.00 €");
+        assert_eq!(get_coin_title(Coin::Coins200), "2.00 €");
+        assert_eq!(get_coin_title(Coin::Coins400), "4.00 €");
+        assert_eq!(get_coin_title(Coin::Coins600), "6.00 €");
+        assert_eq!(get_coin_title(Coin::Coins800), "8.00 €");
+        assert_eq!(get_coin_title(Coin::TakeCashMoney), "Grab");
     }
 }
 
-pub async fn handle_event_change(
-    event: EventReceived,
-    config: &RequestConfig,
-    buttons: &mut HashMap<String, ActionInstance>,
-    client: &mut StreamDeckClient,
-) {
-    match event {
-        EventReceived::WillAppear(event) => {
-            if !buttons.contains_key(&event.context) {
-                let btn = ActionInstance {
-                    title: "".to_string(),
-                    uuid: event.action.clone(),
-                    state: 0, // state: event.payload.state.unwrap_or(0),
-                    value: 0,
-                    settings: event.payload.settings,
-                };
-                buttons.insert(event.context.clone(), btn);
-                client.transmitter.set_state(event.context.clone(), 0).await;
+pub struct ChangeAction;
 
-                if let Some(button) = buttons.get_mut(&event.context) {
-                    let autolabel = get_value_or_empty(&button.settings, "AutoLabel");
-                    if autolabel == "True" {
-                        let cashchangeselect =
-                            get_value_or_empty(&button.settings, "CashChangeSelect");
-                        let title = get_coin_title(&cashchangeselect);
-
-                        let _ = client
-                            .transmitter
-                            .set_title(
-                                event.context.clone(),
-                                title.to_string(),
-                                StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                                None,
-                            )
-                            .await;
-                    }
-                }
-            }
-        }
-
-        EventReceived::WillDisappear(event) => {
-            if buttons.contains_key(&event.context) {
-                buttons.remove(&event.context);
-            }
-        }
-
-        EventReceived::TitleParametersDidChange(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                if let Some(t) = event.payload.settings.get("title").and_then(|v| v.as_str()) {
-                    button.title = t.to_string();
-                }
-            }
-        }
+#[async_trait(?Send)]
+impl Action for ChangeAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
 
-        EventReceived::KeyDown(event) => if let Some(button) = buttons.get_mut(&event.context) {},
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<ChangeSettings>(settings)
+    }
 
-        EventReceived::KeyUp(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let cashchangeselect = get_value_or_empty(&button.settings, "CashChangeSelect");
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let coin = button.typed_settings::<ChangeSettings>().coin;
 
-                let cmd = format!("sendevent?event={}", cashchangeselect);
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
-            }
-        }
+        let cmd = format!("sendevent?event={}", ctx.profile.event(coin.event_name()));
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
 
-        EventReceived::DidReceiveSettings(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                button.settings = event.payload.settings;
-            }
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let settings = button.typed_settings::<ChangeSettings>();
+        if settings.auto_label {
+            let _ = ctx
+                .client
+                .transmitter
+                .set_title(
+                    ctx.context.to_string(),
+                    get_coin_title(settings.coin),
+                    StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                    None,
+                )
+                .await;
         }
-
-        _ => {}
     }
 }
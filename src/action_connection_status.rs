@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rusty_patio::streamdeck::generic::StreamDeckTarget;
+use tokio::sync::Notify;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext, Telemetry};
+use super::telemetry::ConnectionStatus;
+use super::ActionInstance;
+
+pub const UUID: &str = "de.thatzok.thebus.connectionstatus";
+
+/// Button state for each status, see the states in manifest.json.
+pub fn get_status_state(status: ConnectionStatus) -> u8 {
+    match status {
+        ConnectionStatus::Unreachable => 0,
+        ConnectionStatus::NoVehicle => 1,
+        ConnectionStatus::Connected => 2,
+    }
+}
+
+/// Latency in ms, rounded to 10 ms so the title does not flicker on every tick.
+pub fn get_latency_ms(latency: Option<Duration>) -> i64 {
+    match latency {
+        Some(latency) => ((latency.as_millis() as i64 + 5) / 10) * 10,
+        None => 0,
+    }
+}
+
+pub fn get_status_title(status: ConnectionStatus, vehicle_model: &str, latency_ms: i64) -> String {
+    match status {
+        ConnectionStatus::Unreachable => "API\noffline".to_string(),
+        ConnectionStatus::NoVehicle => "No bus".to_string(),
+        ConnectionStatus::Connected => format!("{}\n{} ms", vehicle_model, latency_ms),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_latency_ms() {
+        assert_eq!(get_latency_ms(None), 0);
+        assert_eq!(get_latency_ms(Some(Duration::from_millis(3))), 0);
+        assert_eq!(get_latency_ms(Some(Duration::from_millis(14))), 10);
+        assert_eq!(get_latency_ms(Some(Duration::from_millis(15))), 20);
+        assert_eq!(get_latency_ms(Some(Duration::from_millis(1234))), 1230);
+    }
+
+    #[test]
+    fn test_get_status_title() {
+        assert_eq!(get_status_title(ConnectionStatus::Unreachable, "", 0), "API\noffline");
+        assert_eq!(get_status_title(ConnectionStatus::NoVehicle, "", 0), "No bus");
+        assert_eq!(
+            get_status_title(ConnectionStatus::Connected, "Citea LLE", 20),
+            "Citea LLE\n20 ms"
+        );
+    }
+}
+
+pub struct ConnectionStatusAction {
+    rediscover: Arc<Notify>,
+}
+
+impl ConnectionStatusAction {
+    pub fn new(rediscover: Arc<Notify>) -> Self {
+        ConnectionStatusAction { rediscover }
+    }
+}
+
+#[async_trait(?Send)]
+impl Action for ConnectionStatusAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        ctx.client
+            .transmitter
+            .set_state(ctx.context.to_string(), 0)
+            .await;
+        // no latency is ever negative, so the next telemetry update draws the title
+        button.value = -1;
+    }
+
+    async fn on_key_down(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {
+        // the telemetry task looks for the current vehicle again right away
+        self.rediscover.notify_one();
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let state = get_status_state(telemetry.status);
+        let latency_ms = get_latency_ms(telemetry.latency);
+        if button.state == state && button.value == latency_ms {
+            return;
+        }
+        button.state = state;
+        button.value = latency_ms;
+
+        ctx.client
+            .transmitter
+            .set_state(ctx.context.to_string(), state)
+            .await;
+        let _ = ctx
+            .client
+            .transmitter
+            .set_title(
+                ctx.context.to_string(),
+                get_status_title(telemetry.status, telemetry.vehicle_model, latency_ms),
+                StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                None,
+            )
+            .await;
+    }
+}
@@ -0,0 +1,105 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::de::Error;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext};
+use super::settings::{parse_settings, CustomSettings, CustomType};
+use super::{logger, show_alert_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.customaction";
+
+/// Commands to send on key down and key up, None if there is nothing to send.
+pub fn get_custom_commands(settings: &CustomSettings) -> (Option<String>, Option<String>) {
+    let data = settings.data.trim();
+    if data.is_empty() {
+        return (None, None);
+    }
+    match settings.kind {
+        CustomType::Event => (
+            Some(format!("sendeventpress?event={}", data)),
+            Some(format!("sendeventrelease?event={}", data)),
+        ),
+        CustomType::Cmd => (Some(data.to_string()), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(kind: CustomType, data: &str) -> CustomSettings {
+        CustomSettings {
+            kind,
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_get_custom_commands_event() {
+        assert_eq!(
+            get_custom_commands(&custom(CustomType::Event, " Kneeling ")),
+            (
+                Some("sendeventpress?event=Kneeling".to_string()),
+                Some("sendeventrelease?event=Kneeling".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_custom_commands_cmd() {
+        assert_eq!(
+            get_custom_commands(&custom(CustomType::Cmd, "sendevent?event=Ramp")),
+            (Some("sendevent?event=Ramp".to_string()), None)
+        );
+        assert_eq!(get_custom_commands(&custom(CustomType::Cmd, "")), (None, None));
+    }
+}
+
+async fn send_or_alert(ctx: &mut ActionContext<'_>, cmd: Option<String>, required: bool) {
+    let failed = match cmd {
+        Some(cmd) => {
+            let result = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+            if result.is_err() {
+                logger(&format!("Custom command {} failed", cmd));
+            }
+            result.is_err()
+        }
+        // key down on a button that has not been configured yet
+        None => required,
+    };
+    if failed {
+        show_alert_for_button(ctx).await;
+    }
+}
+
+pub struct CustomAction;
+
+#[async_trait(?Send)]
+impl Action for CustomAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        let settings = parse_settings::<CustomSettings>(settings)?;
+        settings.validate().map_err(serde_json::Error::custom)?;
+        Ok(Box::new(settings))
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let (down, _) = get_custom_commands(&button.typed_settings::<CustomSettings>());
+        send_or_alert(ctx, down, true).await;
+    }
+
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let (_, up) = get_custom_commands(&button.typed_settings::<CustomSettings>());
+        send_or_alert(ctx, up, false).await;
+    }
+}
@@ -0,0 +1,214 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rusty_patio::streamdeck::generic::StreamDeckTarget;
+use serde::de::Error;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::KeyImage;
+use super::settings::{parse_settings, ButtonFunctionType, CustomButtonSettings, FeedbackType};
+use super::{logger, set_image_for_button, set_state_for_button, show_alert_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.custombutton";
+
+fn get_button_command(data: &str) -> Option<String> {
+    // validated when the settings were parsed
+    let command = CustomButtonSettings::button_command(data).ok()??;
+    Some(format!("setbutton?button={}&state={}", command.button, command.state))
+}
+
+/// Commands to send on key down and key up.
+/// An event only given for press is held down until the key is released.
+pub fn get_custom_button_commands(
+    settings: &CustomButtonSettings,
+) -> (Option<String>, Option<String>) {
+    let press = settings.on_press.trim();
+    let release = settings.on_release.trim();
+    match settings.function {
+        ButtonFunctionType::Event => match (press.is_empty(), release.is_empty()) {
+            (true, true) => (None, None),
+            (false, true) => (
+                Some(format!("sendeventpress?event={}", press)),
+                Some(format!("sendeventrelease?event={}", press)),
+            ),
+            (true, false) => (None, Some(format!("sendevent?event={}", release))),
+            (false, false) => (
+                Some(format!("sendevent?event={}", press)),
+                Some(format!("sendevent?event={}", release)),
+            ),
+        },
+        ButtonFunctionType::Button => (get_button_command(press), get_button_command(release)),
+    }
+}
+
+/// 1 if the configured button or light is in its true state.
+pub fn get_feedback_state(settings: &CustomButtonSettings, telemetry: &Telemetry<'_>) -> u8 {
+    let source = match settings.feedback {
+        FeedbackType::Off => return 0,
+        FeedbackType::Button => telemetry.buttons,
+        FeedbackType::Light => telemetry.lights,
+    };
+    let true_state = match settings.true_state.trim() {
+        "" => "On",
+        state => state,
+    };
+    match source.get(settings.source.trim()) {
+        Some(state) if state.eq_ignore_ascii_case(true_state) => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::ConnectionStatus;
+    use komsi::vehicle::VehicleState;
+
+    #[test]
+    fn test_get_custom_button_commands() {
+        let mut settings = CustomButtonSettings {
+            on_press: "MotorStartStop".to_string(),
+            ..CustomButtonSettings::default()
+        };
+        assert_eq!(
+            get_custom_button_commands(&settings),
+            (
+                Some("sendeventpress?event=MotorStartStop".to_string()),
+                Some("sendeventrelease?event=MotorStartStop".to_string())
+            )
+        );
+
+        settings.on_release = "Kneeling".to_string();
+        assert_eq!(
+            get_custom_button_commands(&settings),
+            (
+                Some("sendevent?event=MotorStartStop".to_string()),
+                Some("sendevent?event=Kneeling".to_string())
+            )
+        );
+
+        settings.function = ButtonFunctionType::Button;
+        settings.on_press = "{\"button\":\"GearSwitch\", \"state\":\"0\"}".to_string();
+        settings.on_release = "".to_string();
+        assert_eq!(
+            get_custom_button_commands(&settings),
+            (Some("setbutton?button=GearSwitch&state=0".to_string()), None)
+        );
+    }
+
+    #[test]
+    fn test_get_feedback_state() {
+        let vehicle_state = VehicleState::default();
+        let buttons = HashMap::from([("Door Clearance".to_string(), "Off".to_string())]);
+        let lights = HashMap::from([("ButtonLight Door 1".to_string(), "On".to_string())]);
+        let telemetry = Telemetry {
+            in_bus: true,
+            status: ConnectionStatus::Connected,
+            vehicle_model: "",
+            latency: None,
+            vehicle_state: &vehicle_state,
+            buttons: &buttons,
+            lights: &lights,
+            payment: None,
+            blink: false,
+        };
+
+        let mut settings = CustomButtonSettings {
+            feedback: FeedbackType::Light,
+            source: "ButtonLight Door 1".to_string(),
+            ..CustomButtonSettings::default()
+        };
+        assert_eq!(get_feedback_state(&settings, &telemetry), 1);
+
+        settings.feedback = FeedbackType::Button;
+        settings.source = "Door Clearance".to_string();
+        assert_eq!(get_feedback_state(&settings, &telemetry), 0);
+        settings.true_state = "off".to_string();
+        assert_eq!(get_feedback_state(&settings, &telemetry), 1);
+
+        settings.feedback = FeedbackType::Off;
+        assert_eq!(get_feedback_state(&settings, &telemetry), 0);
+    }
+}
+
+async fn send_command(ctx: &mut ActionContext<'_>, cmd: Option<String>) {
+    if let Some(cmd) = cmd {
+        if send_telemetry_bus_cmd(ctx.config, &cmd).await.is_err() {
+            logger(&format!("Custom button command {} failed", cmd));
+            show_alert_for_button(ctx).await;
+        }
+    }
+}
+
+pub struct CustomButtonAction;
+
+#[async_trait(?Send)]
+impl Action for CustomButtonAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        let settings = parse_settings::<CustomButtonSettings>(settings)?;
+        settings.validate().map_err(serde_json::Error::custom)?;
+        Ok(Box::new(settings))
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let (down, _) = get_custom_button_commands(&button.typed_settings::<CustomButtonSettings>());
+        send_command(ctx, down).await;
+    }
+
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let (_, up) = get_custom_button_commands(&button.typed_settings::<CustomButtonSettings>());
+        send_command(ctx, up).await;
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let state = get_feedback_state(&button.typed_settings::<CustomButtonSettings>(), telemetry);
+        if button.state != state {
+            set_state_for_button(ctx, button, state).await;
+            self.render(ctx, button).await;
+        }
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let settings = button.typed_settings::<CustomButtonSettings>();
+        let (icon, label) = if button.state == 1 {
+            (settings.true_icon, settings.true_label)
+        } else {
+            (settings.default_icon, settings.default_label)
+        };
+
+        // empty resets the key to the image of its state and the user's title,
+        // the other state may have drawn its own before
+        let image = if icon.is_empty() {
+            "".to_string()
+        } else {
+            // embedded, the icon is a file anywhere on the user's disk
+            KeyImage::new().icon(&icon).to_data_url()
+        };
+        set_image_for_button(ctx, image).await;
+        let _ = ctx
+            .client
+            .transmitter
+            .set_title(
+                ctx.context.to_string(),
+                label,
+                StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                None,
+            )
+            .await;
+    }
+}
@@ -0,0 +1,320 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde_json::json;
+use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Access items from the parent module (main.rs)
+use super::profiles::VehicleProfile;
+use super::registry::{Action, ActionContext};
+use super::settings::{parse_boxed_settings, DialFunction, DialSettings};
+use super::{set_feedback_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.dial";
+
+/// How a dial function maps to steps in the game.
+///
+/// The game does not report these positions, so the position shown is not synced with the game:
+/// it is counted from the steps the dial sent successfully. Moving the lever in the game
+/// (or a step the game ignored) is not seen, pressing the dial then moves to a wrong position.
+///
+/// Experimental: the event names and the ranges are assumed, they are not checked against the
+/// game. An event the game does not know is ignored, the dial then counts steps that never moved.
+pub struct DialSpec {
+    pub title: &'static str,
+    pub event_up: &'static str,
+    pub event_down: &'static str,
+    pub min: i64,
+    pub max: i64,
+    /// where the lever is assumed to be when the dial appears, and where pressing the dial moves it
+    pub rest: i64,
+}
+
+pub fn get_dial_spec(function: DialFunction) -> DialSpec {
+    match function {
+        DialFunction::Retarder => DialSpec {
+            title: "Retarder",
+            event_up: "RetarderUp",
+            event_down: "RetarderDown",
+            min: 0,
+            max: 5,
+            rest: 0,
+        },
+        DialFunction::Wiper => DialSpec {
+            title: "Wiper",
+            event_up: "WiperUp",
+            event_down: "WiperDown",
+            min: 0,
+            max: 3,
+            rest: 0,
+        },
+        DialFunction::Climate => DialSpec {
+            title: "Climate",
+            event_up: "ClimateTemperatureUp",
+            event_down: "ClimateTemperatureDown",
+            min: 16,
+            max: 28,
+            rest: 21,
+        },
+    }
+}
+
+pub fn get_dial_value_text(function: DialFunction, position: i64) -> String {
+    match function {
+        DialFunction::Retarder => format!("{} / {}", position, get_dial_spec(function).max),
+        DialFunction::Wiper => match position {
+            0 => "Off".to_string(),
+            1 => "Interval".to_string(),
+            2 => "Slow".to_string(),
+            _ => "Fast".to_string(),
+        },
+        DialFunction::Climate => format!("{} °C", position),
+    }
+}
+
+/// The event for one step from `sent` towards `wanted`, `None` once the lever is there.
+pub fn get_dial_event(
+    function: DialFunction,
+    profile: &VehicleProfile,
+    sent: i64,
+    wanted: i64,
+) -> Option<String> {
+    let spec = get_dial_spec(function);
+    let event = match wanted.cmp(&sent) {
+        Ordering::Greater => spec.event_up,
+        Ordering::Less => spec.event_down,
+        Ordering::Equal => return None,
+    };
+    Some(format!("sendevent?event={}", profile.event(event)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_dial_event() {
+        let profile = VehicleProfile::default();
+        assert_eq!(
+            get_dial_event(DialFunction::Retarder, &profile, 0, 2).as_deref(),
+            Some("sendevent?event=RetarderUp")
+        );
+        assert_eq!(
+            get_dial_event(DialFunction::Climate, &profile, 21, 19).as_deref(),
+            Some("sendevent?event=ClimateTemperatureDown")
+        );
+        assert_eq!(get_dial_event(DialFunction::Wiper, &profile, 1, 1), None);
+    }
+
+    #[test]
+    fn test_get_dial_value_text() {
+        assert_eq!(get_dial_value_text(DialFunction::Retarder, 2), "2 / 5");
+        assert_eq!(get_dial_value_text(DialFunction::Wiper, 1), "Interval");
+        assert_eq!(get_dial_value_text(DialFunction::Climate, 21), "21 °C");
+    }
+}
+
+/// Where a dial is, as far as the plugin knows.
+#[derive(Debug, Clone, Copy)]
+pub struct DialPosition {
+    /// the position the dial was turned to, shown on the touch strip
+    pub wanted: i64,
+    /// the position the steps sent so far have moved the lever to
+    pub sent: i64,
+    /// whether a task is sending steps, it clears this under the lock when it stops
+    pub sending: bool,
+}
+
+/// Sends one step after the other until the lever is at the wanted position. The dial may be
+/// turned on meanwhile, the steps always head for the latest position.
+///
+/// A step that could not be sent ends it with an error, the dial is set back to where the
+/// steps got the lever.
+pub async fn send_dial_steps(
+    function: DialFunction,
+    profile: VehicleProfile,
+    config: RequestConfig,
+    position: Arc<Mutex<DialPosition>>,
+) -> Result<(), String> {
+    loop {
+        let (cmd, step) = {
+            let mut position = position.lock().unwrap();
+            let cmd = get_dial_event(function, &profile, position.sent, position.wanted);
+            position.sending = cmd.is_some();
+            (cmd, (position.wanted - position.sent).signum())
+        };
+        let Some(cmd) = cmd else {
+            return Ok(());
+        };
+        if send_telemetry_bus_cmd(&config, &cmd).await.is_err() {
+            let mut position = position.lock().unwrap();
+            position.wanted = position.sent;
+            position.sending = false;
+            return Err(format!("command {} could not be sent", cmd));
+        }
+        position.lock().unwrap().sent += step;
+    }
+}
+
+struct Dial {
+    position: Arc<Mutex<DialPosition>>,
+    task: Option<JoinHandle<()>>,
+}
+
+/// Counts the position of every dial, see `DialSpec`.
+///
+/// The steps are sent in a task of each dial, so a fast spin never blocks the other keys.
+/// Failed steps are reported on `failed` with the context of their dial, the main loop shows
+/// the alert and draws the dial again. A dial that disappears (e.g. with a page switch) is
+/// forgotten, it starts at rest when it comes back.
+pub struct DialAction {
+    failed: mpsc::UnboundedSender<(String, String)>,
+    dials: RefCell<HashMap<String, Dial>>,
+}
+
+impl DialAction {
+    pub fn new(failed: mpsc::UnboundedSender<(String, String)>) -> Self {
+        DialAction {
+            failed,
+            dials: RefCell::new(HashMap::new()),
+        }
+    }
+
+    async fn move_to(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance, ticks: i64) {
+        let function = button.typed_settings::<DialSettings>().function;
+        let spec = get_dial_spec(function);
+        {
+            let mut dials = self.dials.borrow_mut();
+            let Some(dial) = dials.get_mut(ctx.context) else {
+                return;
+            };
+            let mut position = dial.position.lock().unwrap();
+            let wanted = (position.wanted + ticks).clamp(spec.min, spec.max);
+            if wanted == position.wanted {
+                return;
+            }
+            position.wanted = wanted;
+            // a running task picks up the new position by itself
+            if !position.sending {
+                position.sending = true;
+                // the task outlives this event, so it gets its own config for the current target
+                let mut config = RequestConfig::new();
+                config.ip = ctx.config.ip.clone();
+                config.port = ctx.config.port;
+                config.vehicle_name = ctx.config.vehicle_name.clone();
+                let steps =
+                    send_dial_steps(function, ctx.profile.clone(), config, dial.position.clone());
+                let context = ctx.context.to_string();
+                let failed = self.failed.clone();
+                dial.task = Some(tokio::spawn(async move {
+                    if let Err(err) = steps.await {
+                        let _ = failed.send((context, err));
+                    }
+                }));
+            }
+        }
+        self.render(ctx, button).await;
+    }
+
+    async fn move_to_rest(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let spec = get_dial_spec(button.typed_settings::<DialSettings>().function);
+        let wanted = self
+            .dials
+            .borrow()
+            .get(ctx.context)
+            .map_or(spec.rest, |dial| dial.position.lock().unwrap().wanted);
+        self.move_to(ctx, button, spec.rest - wanted).await;
+    }
+}
+
+#[async_trait(?Send)]
+impl Action for DialAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<DialSettings>(settings)
+    }
+
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        // nothing is sent here, the lever is assumed to be at rest
+        let rest = get_dial_spec(button.typed_settings::<DialSettings>().function).rest;
+        let dial = Dial {
+            position: Arc::new(Mutex::new(DialPosition {
+                wanted: rest,
+                sent: rest,
+                sending: false,
+            })),
+            task: None,
+        };
+        self.dials.borrow_mut().insert(ctx.context.to_string(), dial);
+        self.render(ctx, button).await;
+    }
+
+    async fn on_will_disappear(&self, ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {
+        let dial = self.dials.borrow_mut().remove(ctx.context);
+        if let Some(task) = dial.and_then(|dial| dial.task) {
+            task.abort();
+        }
+    }
+
+    async fn on_dial_rotate(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        ticks: i64,
+    ) {
+        self.move_to(ctx, button, ticks).await;
+    }
+
+    async fn on_dial_press(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        pressed: bool,
+    ) {
+        if pressed {
+            self.move_to_rest(ctx, button).await;
+        }
+    }
+
+    async fn on_touch_tap(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.move_to_rest(ctx, button).await;
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let function = button.typed_settings::<DialSettings>().function;
+        let spec = get_dial_spec(function);
+        if let Some(dial) = self.dials.borrow().get(ctx.context) {
+            let mut position = dial.position.lock().unwrap();
+            // the function may just have been changed in the property inspector,
+            // the lever is assumed to be at the nearest end then, nothing is sent
+            let wanted = position.wanted.clamp(spec.min, spec.max);
+            if wanted != position.wanted {
+                position.wanted = wanted;
+                position.sent = wanted;
+            }
+            button.value = wanted;
+        }
+        let percent = (button.value - spec.min) * 100 / (spec.max - spec.min);
+        set_feedback_for_button(
+            ctx,
+            json!({
+                "title": spec.title,
+                "value": get_dial_value_text(function, button.value),
+                "indicator": { "value": percent },
+            }),
+        )
+        .await;
+    }
+}
@@ -1,125 +1,132 @@
+use std::any::Any;
 use std::collections::HashMap;
 
-use rusty_patio::streamdeck::generic::StreamDeckTarget;
-use rusty_patio::streamdeck::{client::StreamDeckClient, events::event_received::EventReceived};
-use the_bus_telemetry::api::{RequestConfig, send_telemetry_bus_cmd};
+use async_trait::async_trait;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+use tokio::sync::watch;
+
 // Access items from the parent module (main.rs)
-use super::{ActionInstance, get_value_or_empty, logger};
-
-pub async fn handle_event_fixing_gearselect(
-    event: EventReceived,
-    config: &RequestConfig,
-    buttons: &mut HashMap<String, ActionInstance>,
-    client: &mut StreamDeckClient,
-) {
-    match event {
-        EventReceived::WillAppear(event) => {
-            if !buttons.contains_key(&event.context) {
-                let btn = ActionInstance {
-                    title: "".to_string(),
-                    uuid: event.action.clone(),
-                    state: 0, // state: event.payload.state.unwrap_or(0),
-                    value: 0,
-                    settings: event.payload.settings,
-                };
-                buttons.insert(event.context.clone(), btn);
-                client.transmitter.set_state(event.context.clone(), 0).await;
-
-                if let Some(button) = buttons.get_mut(&event.context) {
-                    let mut gear = get_value_or_empty(&button.settings, "GearSelection");
-                    if gear.is_empty() {
-                        gear = "2".to_string();
-                    }
-
-                    let mut active = "off";
-                    if button.state.to_string() == gear {
-                        active = "on";
-                    } else {
-                        active = "off";
-                    }
-
-                    let g = match gear.as_str() {
-                        "1" => "D",
-                        "2" => "N",
-                        "3" => "R",
-                        _ => "N",
-                    };
-
-                    let image = format!("actions/assets/gear_{}_off.png", g);
-
-                    let _ = client
-                        .transmitter
-                        .set_image(
-                            event.context.clone(),
-                            image,
-                            StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                            None,
-                        )
-                        .await;
-                }
-            }
-        }
+use super::interlock::Interlock;
+use super::registry::{Action, ActionContext};
+use super::render::{KeyImage, COLOR_BACKGROUND};
+use super::settings::{parse_boxed_settings, Gear, GearSettings};
+use super::state_diff::StateChange;
+use super::telemetry::TelemetrySnapshot;
+use super::theme::Theme;
+use super::{logger, ActionInstance, set_image_for_button, show_alert_for_button};
+
+pub const UUID: &str = "de.thatzok.thebus.gearselect";
+
+/// The gear button with its lamp lit while the gear is engaged.
+pub fn get_gear_image(theme: &Theme, gear: Gear, active: bool) -> KeyImage {
+    KeyImage::new()
+        .background(COLOR_BACKGROUND)
+        .icon(&theme.icon(&format!("gear_{}_off.png", gear.letter())))
+        .lamp(active)
+}
 
-        EventReceived::WillDisappear(event) => {
-            if buttons.contains_key(&event.context) {
-                buttons.remove(&event.context);
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::COLOR_LAMP_ON;
+
+    #[test]
+    fn test_get_gear_image() {
+        let theme = Theme::default();
+        assert!(get_gear_image(&theme, Gear::D, true).to_svg().contains(COLOR_LAMP_ON));
+        assert!(!get_gear_image(&theme, Gear::R, false).to_svg().contains(COLOR_LAMP_ON));
+        assert_eq!(get_gear_image(&theme, Gear::N, true), get_gear_image(&theme, Gear::N, true));
+    }
+}
 
-        EventReceived::TitleParametersDidChange(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                if let Some(t) = event.payload.settings.get("title").and_then(|v| v.as_str()) {
-                    button.title = t.to_string();
-                }
-            }
-        }
+/// Follows the gear selector through `GearChanged`, `telemetry` is only read when the key
+/// appears or missed changes, to start with the gear that is engaged.
+pub struct GearSelectAction {
+    interlock: Interlock,
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+}
 
-        EventReceived::KeyDown(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let mut gear = get_value_or_empty(&button.settings, "GearSelection");
-                if gear.is_empty() {
-                    gear = "2".to_string();
-                }
+impl GearSelectAction {
+    pub fn new(interlock: Interlock, telemetry: watch::Receiver<TelemetrySnapshot>) -> Self {
+        GearSelectAction { interlock, telemetry }
+    }
 
-                let g = match gear.as_str() {
-                    "1" => "D",
-                    "2" => "N",
-                    "3" => "R",
-                    _ => "N",
-                };
+    fn read_gear(&self, button: &mut ActionInstance) {
+        button.state = self.telemetry.borrow().vehicle_state.gear_selector;
+    }
+}
 
-                let cmd = format!("sendeventpress?event=SetGear{}", g);
+#[async_trait(?Send)]
+impl Action for GearSelectAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
 
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
-            }
-        }
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<GearSettings>(settings)
+    }
+
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_gear(button);
+        self.render(ctx, button).await;
+    }
 
-        EventReceived::KeyUp(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let mut gear = get_value_or_empty(&button.settings, "GearSelection");
-                if gear.is_empty() {
-                    gear = "2".to_string();
-                }
+    async fn on_changes_missed(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_gear(button);
+        self.render(ctx, button).await;
+    }
 
-                let g = match gear.as_str() {
-                    "1" => "D",
-                    "2" => "N",
-                    "3" => "R",
-                    _ => "N",
-                };
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let gear = button.typed_settings::<GearSettings>().gear;
+        if !ctx.profile.has_gear(gear) {
+            show_alert_for_button(ctx).await;
+            return;
+        }
+        if let Err(reason) = self.interlock.check_gear(gear) {
+            logger(&format!("Interlock: {}", reason));
+            show_alert_for_button(ctx).await;
+            return;
+        }
+        let event = format!("SetGear{}", gear.letter());
+        let cmd = format!("sendeventpress?event={}", ctx.profile.event(&event));
 
-                let cmd = format!("sendeventrelease?event=SetGear{}", g);
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
 
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
-            }
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let gear = button.typed_settings::<GearSettings>().gear;
+        if !ctx.profile.has_gear(gear) {
+            return;
         }
+        let event = format!("SetGear{}", gear.letter());
+        let cmd = format!("sendeventrelease?event={}", ctx.profile.event(&event));
+
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
 
-        EventReceived::DidReceiveSettings(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                button.settings = event.payload.settings;
-            }
+    async fn on_state_change(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        change: &StateChange,
+    ) {
+        if let StateChange::GearChanged { gear } = *change {
+            // 0 while the selector is between the gears, no lamp is lit then
+            button.state = gear.map_or(0, |gear| gear.state());
+            self.render(ctx, button).await;
         }
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let gear = button.typed_settings::<GearSettings>().gear;
 
-        _ => {}
+        let mut image = get_gear_image(ctx.theme, gear, button.state == gear.state());
+        if !ctx.profile.has_gear(gear) {
+            image = image.unavailable();
+        }
+        set_image_for_button(ctx, image.to_data_url()).await;
     }
 }
@@ -0,0 +1,163 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use komsi::vehicle::VehicleState;
+use tokio::sync::watch;
+
+// Access items from the parent module (main.rs)
+use super::gauge::bar_gauge;
+use super::profiles::VehicleProfile;
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::svg_data_url;
+use super::settings::{parse_boxed_settings, FuelSettings};
+use super::telemetry::TelemetrySnapshot;
+use super::{set_image_for_button, set_title_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.fuel";
+
+/// Electric buses report their battery charge as fuel level.
+pub fn get_fuel_label(profile: &VehicleProfile) -> &'static str {
+    if profile.electric {
+        "Battery"
+    } else {
+        "Fuel"
+    }
+}
+
+/// The warning flashes: red on every other tick while the level is below the threshold.
+pub fn get_fuel_alarm(level: i64, threshold: u8, blink: bool) -> bool {
+    level < threshold as i64 && blink
+}
+
+/// Outside of a bus the game reports no level, so the gauge stays empty instead of showing 0%.
+fn get_fuel_gauge(in_bus: bool, state: &VehicleState, threshold: u8, blink: bool) -> FuelGauge {
+    if !in_bus {
+        return FuelGauge::NoBus;
+    }
+    // komsi reports the level in percent
+    let percent = (state.fuel as i64).clamp(0, 100);
+    FuelGauge::Level {
+        percent,
+        alarm: get_fuel_alarm(percent, threshold, blink),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::ProfileDatabase;
+
+    #[test]
+    fn test_get_fuel_label() {
+        let profiles = ProfileDatabase::embedded();
+        assert_eq!(get_fuel_label(profiles.find("MAN Lion's City 12m").unwrap()), "Fuel");
+        assert_eq!(get_fuel_label(profiles.find("MAN Lion's City E").unwrap()), "Battery");
+        assert_eq!(get_fuel_label(profiles.find("Mercedes-Benz eCitaro").unwrap()), "Battery");
+        assert_eq!(get_fuel_label(&VehicleProfile::default()), "Fuel");
+    }
+
+    #[test]
+    fn test_get_fuel_alarm() {
+        assert!(get_fuel_alarm(10, 15, true));
+        assert!(!get_fuel_alarm(10, 15, false));
+        assert!(!get_fuel_alarm(15, 15, true));
+        assert!(!get_fuel_alarm(0, 0, true));
+    }
+
+    #[test]
+    fn test_get_fuel_gauge() {
+        let state = VehicleState {
+            fuel: 10,
+            ..VehicleState::default()
+        };
+        assert_eq!(
+            get_fuel_gauge(true, &state, 15, true),
+            FuelGauge::Level {
+                percent: 10,
+                alarm: true
+            }
+        );
+        assert_eq!(get_fuel_gauge(false, &VehicleState::default(), 15, true), FuelGauge::NoBus);
+    }
+}
+
+/// What the key shows, to draw it only when that changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FuelGauge {
+    /// no bus, no level: an empty gauge without title
+    NoBus,
+    Level { percent: i64, alarm: bool },
+}
+
+pub struct FuelAction {
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+    /// the gauge last drawn on each key
+    drawn: RefCell<HashMap<String, FuelGauge>>,
+}
+
+impl FuelAction {
+    pub fn new(telemetry: watch::Receiver<TelemetrySnapshot>) -> Self {
+        FuelAction {
+            telemetry,
+            drawn: RefCell::new(HashMap::new()),
+        }
+    }
+
+    async fn draw(&self, ctx: &mut ActionContext<'_>, gauge: FuelGauge) {
+        let label = get_fuel_label(ctx.profile);
+        let (image, title) = match gauge {
+            FuelGauge::NoBus => (bar_gauge(0.0, label, false), "".to_string()),
+            FuelGauge::Level { percent, alarm } => {
+                (bar_gauge(percent as f64, label, alarm), format!("{}%", percent))
+            }
+        };
+        set_image_for_button(ctx, svg_data_url(&image)).await;
+        set_title_for_button(ctx, title).await;
+        self.drawn.borrow_mut().insert(ctx.context.to_string(), gauge);
+    }
+}
+
+#[async_trait(?Send)]
+impl Action for FuelAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<FuelSettings>(settings)
+    }
+
+    async fn on_will_disappear(&self, ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {
+        self.drawn.borrow_mut().remove(ctx.context);
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let threshold = button.typed_settings::<FuelSettings>().warning_threshold;
+        let state = telemetry.vehicle_state;
+        let gauge = get_fuel_gauge(telemetry.in_bus, state, threshold, telemetry.blink);
+        if self.drawn.borrow().get(ctx.context) != Some(&gauge) {
+            self.draw(ctx, gauge).await;
+        }
+    }
+
+    /// Draws the gauge from the latest telemetry, e.g. with a new warning threshold.
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let threshold = button.typed_settings::<FuelSettings>().warning_threshold;
+        let gauge = {
+            let snapshot = self.telemetry.borrow();
+            // a warning starts red, the next tick makes it flash
+            get_fuel_gauge(snapshot.in_bus, &snapshot.vehicle_state, threshold, true)
+        };
+        self.draw(ctx, gauge).await;
+    }
+}
@@ -1,34 +1,36 @@
+use std::any::Any;
 use std::collections::HashMap;
 
+use async_trait::async_trait;
+use komsi::vehicle::VehicleState;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+use tokio::sync::watch;
+
 // Access items from the parent module (main.rs)
-use super::{get_value_or_empty, ActionInstance};
-use rusty_patio::streamdeck::generic::StreamDeckTarget;
-use rusty_patio::streamdeck::{client::StreamDeckClient, events::event_received::EventReceived};
-use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
-
-pub fn get_indicator_image_on(indicatorselector: &str) -> String {
-    let mut d = "";
-
-    d = match indicatorselector {
-        "IndicatorRight" => "actions/assets/indicator_right_on.png",
-        "IndicatorLeft" => "actions/assets/indicator_left_on.png",
-        "WarningLights" => "actions/assets/warninglights_on.png",
-        _ => "actions/assets/warninglights_on.png",
-    };
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::KeyImage;
+use super::settings::{parse_boxed_settings, IndicatorSelector, IndicatorSettings};
+use super::state_diff::StateChange;
+use super::telemetry::TelemetrySnapshot;
+use super::theme::Theme;
+use super::{set_image_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.indicatorcontrol";
+
+fn get_indicator_icon(indicatorselector: IndicatorSelector) -> &'static str {
+    match indicatorselector {
+        IndicatorSelector::IndicatorRight => "indicator_right",
+        IndicatorSelector::IndicatorLeft => "indicator_left",
+        IndicatorSelector::WarningLights => "warninglights",
+    }
+}
 
-    d.to_string()
+pub fn get_indicator_image_on(theme: &Theme, indicatorselector: IndicatorSelector) -> String {
+    theme.icon(&format!("{}_on.png", get_indicator_icon(indicatorselector)))
 }
-pub fn get_indicator_image_off(indicatorselector: &str) -> String {
-    let mut d = "";
-
-    d = match indicatorselector {
-        "IndicatorRight" => "actions/assets/indicator_right_off.png",
-        "IndicatorLeft" => "actions/assets/indicator_left_off.png",
-        "WarningLights" => "actions/assets/warninglights_off.png",
-        _ => "actions/assets/warninglights_on.png",
-    };
 
-    d.to_string()
+pub fn get_indicator_image_off(theme: &Theme, indicatorselector: IndicatorSelector) -> String {
+    theme.icon(&format!("{}_off.png", get_indicator_icon(indicatorselector)))
 }
 
 #[cfg(test)]
@@ -37,120 +39,157 @@ mod tests {
 
     #[test]
     fn test_get_indicator_image_on() {
-        assert_eq!(get_indicator_image_on("IndicatorRight"), "actions/assets/indicator_right_on.png");
-        assert_eq!(get_indicator_image_on("IndicatorLeft"), "actions/assets/indicator_left_on.png");
-        assert_eq!(get_indicator_image_on("WarningLights"), "actions/assets/warninglights_on.png");
-        assert_eq!(get_indicator_image_on("Unknown"), "actions/assets/warninglights_on.png");
+        let theme = Theme::default();
+        assert_eq!(get_indicator_image_on(&theme, IndicatorSelector::IndicatorRight), "actions/assets/indicator_right_on.png");
+        assert_eq!(get_indicator_image_on(&theme, IndicatorSelector::IndicatorLeft), "actions/assets/indicator_left_on.png");
+        assert_eq!(get_indicator_image_on(&theme, IndicatorSelector::WarningLights), "actions/assets/warninglights_on.png");
+    }
+
+    #[test]
+    fn test_get_indicator_lamp() {
+        let state = VehicleState {
+            lights_warning: true,
+            ..VehicleState::default()
+        };
+        assert!(is_indicator_on(IndicatorSelector::IndicatorRight, &state));
+        assert!(!is_indicator_on(IndicatorSelector::IndicatorLeft, &VehicleState::default()));
+        assert_eq!(get_indicator_lamp(IndicatorSelector::IndicatorLeft, true, false), 0);
+        assert_eq!(get_indicator_lamp(IndicatorSelector::IndicatorLeft, true, true), 1);
+        // the warning lights key does not blink
+        assert_eq!(get_indicator_lamp(IndicatorSelector::WarningLights, true, false), 1);
     }
 
     #[test]
     fn test_get_indicator_image_off() {
-        assert_eq!(get_indicator_image_off("IndicatorRight"), "actions/assets/indicator_right_off.png");
-        assert_eq!(get_indicator_image_off("IndicatorLeft"), "actions/assets/indicator_left_off.png");
-        assert_eq!(get_indicator_image_off("WarningLights"), "actions/assets/warninglights_off.png");
-        assert_eq!(get_indicator_image_off("Unknown"), "actions/assets/warninglights_on.png");
+        let theme = Theme::default();
+        assert_eq!(get_indicator_image_off(&theme, IndicatorSelector::IndicatorRight), "actions/assets/indicator_right_off.png");
+        assert_eq!(get_indicator_image_off(&theme, IndicatorSelector::IndicatorLeft), "actions/assets/indicator_left_off.png");
+        assert_eq!(get_indicator_image_off(&theme, IndicatorSelector::WarningLights), "actions/assets/warninglights_off.png");
     }
 }
 
-pub async fn handle_event_indicators(
-    event: EventReceived,
-    config: &RequestConfig,
-    buttons: &mut HashMap<String, ActionInstance>,
-    client: &mut StreamDeckClient,
-) {
-    match event {
-        EventReceived::WillAppear(event) => {
-            if !buttons.contains_key(&event.context) {
-                let btn = ActionInstance {
-                    title: "".to_string(),
-                    uuid: event.action.clone(),
-                    state: 0, // state: event.payload.state.unwrap_or(0),
-                    value: 0,
-                    settings: event.payload.settings,
-                };
-                buttons.insert(event.context.clone(), btn);
-                client.transmitter.set_state(event.context.clone(), 0).await;
-
-                if let Some(button) = buttons.get_mut(&event.context) {
-                    let mut indicatorselector = get_value_or_empty(&button.settings, "IndicatorSelector");
-                    if indicatorselector.is_empty() {
-                        indicatorselector = "IndicatorLeft".to_string();
-                    }
-
-                    let mut image = get_indicator_image_off(indicatorselector.as_str());
-
-
-                    let _ = client
-                        .transmitter
-                        .set_image(
-                            event.context.clone(),
-                            image.to_string(),
-                            StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                            None,
-                        )
-                        .await;
-                }
-            }
-        }
+// indicator: left:1, right: 2, none/off: 0
+// lights_warning: 1:on 0:off
+fn is_indicator_on(indicatorselector: IndicatorSelector, state: &VehicleState) -> bool {
+    match indicatorselector {
+        IndicatorSelector::IndicatorLeft => state.indicator == 1 || state.lights_warning,
+        IndicatorSelector::IndicatorRight => state.indicator == 2 || state.lights_warning,
+        IndicatorSelector::WarningLights => state.lights_warning,
+    }
+}
 
-        EventReceived::WillDisappear(event) => {
-            if buttons.contains_key(&event.context) {
-                buttons.remove(&event.context);
-            }
-        }
+/// The lamp of a key, the indicators blink while they are on.
+fn get_indicator_lamp(indicatorselector: IndicatorSelector, on: bool, blink: bool) -> u8 {
+    let lit = match indicatorselector {
+        IndicatorSelector::IndicatorLeft | IndicatorSelector::IndicatorRight => on && blink,
+        IndicatorSelector::WarningLights => on,
+    };
+    if lit { 1 } else { 0 }
+}
 
-        EventReceived::TitleParametersDidChange(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                if let Some(t) = event.payload.settings.get("title").and_then(|v| v.as_str()) {
-                    button.title = t.to_string();
-                }
-            }
-        }
+/// Whether its indicator is on (kept in `button.value`) is read from `telemetry` when the key
+/// appears, after missed changes and on `IndicatorChanged` or `WarningLightsChanged`; the
+/// warning lights light both indicators, so one change alone does not tell. The telemetry
+/// ticks only make it blink.
+pub struct IndicatorsAction {
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+}
 
-        EventReceived::KeyDown(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let mut indicatorselector = get_value_or_empty(&button.settings, "IndicatorSelector");
-                if indicatorselector.is_empty() {
-                    indicatorselector = "IndicatorLeft".to_string();
-                }
+impl IndicatorsAction {
+    pub fn new(telemetry: watch::Receiver<TelemetrySnapshot>) -> Self {
+        IndicatorsAction { telemetry }
+    }
 
+    /// Reads whether the indicator is on, true if the lamp has changed.
+    fn read_indicator(&self, button: &mut ActionInstance) -> bool {
+        let selector = button.typed_settings::<IndicatorSettings>().selector;
+        let on = is_indicator_on(selector, &self.telemetry.borrow().vehicle_state);
+        button.value = if on { 1 } else { 0 };
+        // a blinking indicator starts lit
+        let state = get_indicator_lamp(selector, on, true);
+        let changed = button.state != state;
+        button.state = state;
+        changed
+    }
+}
 
-                let cmd = match indicatorselector.as_str() {
-                    "IndicatorRight" => "sendevent?event=IndicatorUp",
-                    "IndicatorLeft" => "sendevent?event=IndicatorDown",
-                    "WarningLights" => "sendevent?event=ToggleWarningLights",
-                    _ => "sendevent?event=ToggleWarningLights",
-                };
+#[async_trait(?Send)]
+impl Action for IndicatorsAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
 
-                let _ = send_telemetry_bus_cmd(&config, cmd).await;
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<IndicatorSettings>(settings)
+    }
 
-            }
-        }
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_indicator(button);
+        self.render(ctx, button).await;
+    }
 
-        EventReceived::KeyUp(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-/*
-                let mut doorselector = get_value_or_empty(&button.settings, "DoorSelector");
-                if doorselector.is_empty() {
-                    doorselector = "Door 1".to_string();
-                }
+    async fn on_did_receive_settings(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_indicator(button);
+        self.render(ctx, button).await;
+    }
 
-                let d = get_door_action(doorselector.as_str(), &config.vehicle_model);
+    async fn on_changes_missed(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_indicator(button);
+        self.render(ctx, button).await;
+    }
 
-                let cmd = format!("sendeventrelease?event={}", d);
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let event = match button.typed_settings::<IndicatorSettings>().selector {
+            IndicatorSelector::IndicatorRight => "IndicatorUp",
+            IndicatorSelector::IndicatorLeft => "IndicatorDown",
+            IndicatorSelector::WarningLights => "ToggleWarningLights",
+        };
 
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
+        let cmd = format!("sendevent?event={}", ctx.profile.event(event));
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
 
- */
-            }
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let selector = button.typed_settings::<IndicatorSettings>().selector;
+        let state = get_indicator_lamp(selector, button.value == 1, telemetry.blink);
+        if button.state != state {
+            button.state = state;
+            self.render(ctx, button).await;
         }
+    }
 
-        EventReceived::DidReceiveSettings(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                button.settings = event.payload.settings;
-            }
+    async fn on_state_change(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        change: &StateChange,
+    ) {
+        if matches!(
+            change,
+            StateChange::IndicatorChanged { .. } | StateChange::WarningLightsChanged { .. }
+        ) && self.read_indicator(button)
+        {
+            self.render(ctx, button).await;
         }
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let indicatorselector = button.typed_settings::<IndicatorSettings>().selector;
+        let icon = if button.state == 0 {
+            get_indicator_image_off(ctx.theme, indicatorselector)
+        } else {
+            get_indicator_image_on(ctx.theme, indicatorselector)
+        };
 
-        _ => {}
+        // embedded, icons of a user pack are outside of the plugin folder
+        set_image_for_button(ctx, KeyImage::new().icon(&icon).to_data_url()).await;
     }
 }
@@ -0,0 +1,140 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::profiles::{LIGHT_FOG, LIGHT_INTERIOR};
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::{KeyImage, COLOR_BACKGROUND};
+use super::settings::{parse_boxed_settings, LightSelector, LightSettings};
+use super::theme::Theme;
+use super::{set_image_for_button, show_alert_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.lights";
+
+pub fn get_light_event(lightselector: LightSelector) -> &'static str {
+    match lightselector {
+        LightSelector::MainLight => "ToggleMainLight",
+        LightSelector::HighBeam => "ToggleHighBeam",
+        LightSelector::FogLight => "ToggleFogLight",
+        LightSelector::InteriorLight => "ToggleInteriorLight",
+    }
+}
+
+pub fn get_light_image(theme: &Theme, lightselector: LightSelector, active: bool) -> KeyImage {
+    let icon = match lightselector {
+        LightSelector::MainLight => "light_main.svg",
+        LightSelector::HighBeam => "light_high_beam.svg",
+        LightSelector::FogLight => "light_fog.svg",
+        LightSelector::InteriorLight => "light_interior.svg",
+    };
+    KeyImage::new()
+        .background(COLOR_BACKGROUND)
+        .icon(&theme.icon(icon))
+        .lamp(active)
+}
+
+/// Light modes reported by the game are "Off", "On" or e.g. "Blinking".
+fn is_light_on(mode: Option<&String>) -> bool {
+    mode.is_some_and(|mode| !mode.eq_ignore_ascii_case("Off"))
+}
+
+fn get_light_lamp(lightselector: LightSelector, telemetry: &Telemetry<'_>) -> u8 {
+    let state = telemetry.vehicle_state;
+    let lamp = match lightselector {
+        LightSelector::MainLight => state.lights_main,
+        LightSelector::HighBeam => state.lights_high_beam,
+        LightSelector::FogLight => is_light_on(telemetry.lights.get(LIGHT_FOG)),
+        LightSelector::InteriorLight => is_light_on(telemetry.lights.get(LIGHT_INTERIOR)),
+    };
+    if lamp { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::COLOR_LAMP_ON;
+
+    #[test]
+    fn test_get_light_event() {
+        assert_eq!(get_light_event(LightSelector::MainLight), "ToggleMainLight");
+        assert_eq!(get_light_event(LightSelector::HighBeam), "ToggleHighBeam");
+        assert_eq!(get_light_event(LightSelector::FogLight), "ToggleFogLight");
+        assert_eq!(get_light_event(LightSelector::InteriorLight), "ToggleInteriorLight");
+    }
+
+    #[test]
+    fn test_get_light_image() {
+        let theme = Theme::default();
+        assert!(get_light_image(&theme, LightSelector::HighBeam, true).to_svg().contains(COLOR_LAMP_ON));
+        assert!(!get_light_image(&theme, LightSelector::FogLight, false).to_svg().contains(COLOR_LAMP_ON));
+    }
+
+    #[test]
+    fn test_is_light_on() {
+        assert!(is_light_on(Some(&"On".to_string())));
+        assert!(is_light_on(Some(&"Blinking".to_string())));
+        assert!(!is_light_on(Some(&"off".to_string())));
+        // the bus has no such light
+        assert!(!is_light_on(None));
+    }
+}
+
+pub struct LightsAction;
+
+#[async_trait(?Send)]
+impl Action for LightsAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<LightSettings>(settings)
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<LightSettings>().selector;
+        if !ctx.profile.has_light(selector) {
+            show_alert_for_button(ctx).await;
+            return;
+        }
+        let cmd = format!("sendeventpress?event={}", ctx.profile.event(get_light_event(selector)));
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
+
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<LightSettings>().selector;
+        if !ctx.profile.has_light(selector) {
+            return;
+        }
+        let cmd = format!("sendeventrelease?event={}", ctx.profile.event(get_light_event(selector)));
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let state = get_light_lamp(button.typed_settings::<LightSettings>().selector, telemetry);
+        if button.state != state {
+            button.state = state;
+            self.render(ctx, button).await;
+        }
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<LightSettings>().selector;
+        let mut image = get_light_image(ctx.theme, selector, button.state == 1);
+        if !ctx.profile.has_light(selector) {
+            image = image.unavailable();
+        }
+        set_image_for_button(ctx, image.to_data_url()).await;
+    }
+}
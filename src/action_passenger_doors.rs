@@ -1,158 +1,195 @@
+use std::any::Any;
 use std::collections::HashMap;
 
+use async_trait::async_trait;
+use komsi::vehicle::VehicleState;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+use tokio::sync::watch;
+
 // Access items from the parent module (main.rs)
-use super::{get_value_or_empty, ActionInstance};
-use rusty_patio::streamdeck::generic::StreamDeckTarget;
-use rusty_patio::streamdeck::{client::StreamDeckClient, events::event_received::EventReceived};
-use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
-
-pub fn get_door_action(doorselector: &str, model: &str) -> String {
-    let mut d = "";
-
-    if model == "Citea LLE" {
-        d = match doorselector {
-            "Door 1" => "DoorFrontOpenClose",
-            "Door 2" => "MiddleDoorOpenClose",
-            "Door 3" => "RearDoorOpenClose",
-            "Door 4" => "FourthDoorOpenClose",
-            "Clearance" => "ToggleDoorClearance",
-            _ => "DoorFrontOpenClose",
-        }
+use super::interlock::Interlock;
+use super::profiles::VehicleProfile;
+use super::registry::{Action, ActionContext};
+use super::render::{KeyImage, TextPosition, COLOR_BACKGROUND, COLOR_TEXT};
+use super::settings::{parse_boxed_settings, DoorSelector, DoorSettings};
+use super::state_diff::{door_lamp, StateChange};
+use super::telemetry::TelemetrySnapshot;
+use super::theme::Theme;
+use super::{logger, set_image_for_button, show_alert_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.dooraction";
+
+pub fn get_door_action(doorselector: DoorSelector, profile: &VehicleProfile) -> String {
+    let d = match doorselector {
+        DoorSelector::Door1 => "DoorFrontOpenClose",
+        DoorSelector::Door2 => "DoorMiddleOpenClose",
+        DoorSelector::Door3 => "DoorRearOpenClose",
+        DoorSelector::Door4 => "DoorFourthOpenClose",
+        DoorSelector::Clearance => "ToggleDoorClearance",
+    };
+
+    profile.event(d).to_string()
+}
+
+/// The door button with the door number and its lamp.
+pub fn get_door_image(theme: &Theme, doorselector: DoorSelector, active: bool) -> KeyImage {
+    let (icon, label) = match doorselector {
+        DoorSelector::Door1 => ("doorbutton_off.png", "1"),
+        DoorSelector::Door2 => ("doorbutton_off.png", "2"),
+        DoorSelector::Door3 => ("doorbutton_off.png", "3"),
+        DoorSelector::Door4 => ("doorbutton_off.png", "4"),
+        DoorSelector::Clearance => ("doorclearance_off.png", ""),
+    };
+    let image = KeyImage::new()
+        .background(COLOR_BACKGROUND)
+        .icon(&theme.icon(icon))
+        .lamp(active);
+    if label.is_empty() {
+        image
     } else {
-        d = match doorselector {
-            "Door 1" => "DoorFrontOpenClose",
-            "Door 2" => "DoorMiddleOpenClose",
-            "Door 3" => "DoorRearOpenClose",
-            "Door 4" => "DoorFourthOpenClose",
-            "Clearance" => "ToggleDoorClearance",
-            _ => "DoorFrontOpenClose",
-        }
+        image.text(label, COLOR_TEXT, 28, TextPosition::Top)
     }
-
-    d.to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::profiles::ProfileDatabase;
 
     #[test]
     fn test_get_door_action_citea() {
-        let model = "Citea LLE";
-        assert_eq!(get_door_action("Door 1", model), "DoorFrontOpenClose");
-        assert_eq!(get_door_action("Door 2", model), "MiddleDoorOpenClose");
-        assert_eq!(get_door_action("Door 3", model), "RearDoorOpenClose");
-        assert_eq!(get_door_action("Door 4", model), "FourthDoorOpenClose");
-        assert_eq!(get_door_action("Clearance", model), "ToggleDoorClearance");
-        assert_eq!(get_door_action("Unknown", model), "DoorFrontOpenClose");
+        let profiles = ProfileDatabase::embedded();
+        let model = profiles.find("Citea LLE").unwrap();
+        assert_eq!(get_door_action(DoorSelector::Door1, model), "DoorFrontOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door2, model), "MiddleDoorOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door3, model), "RearDoorOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door4, model), "FourthDoorOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Clearance, model), "ToggleDoorClearance");
     }
 
     #[test]
     fn test_get_door_action_other() {
-        let model = "MAN Lion's City";
-        assert_eq!(get_door_action("Door 1", model), "DoorFrontOpenClose");
-        assert_eq!(get_door_action("Door 2", model), "DoorMiddleOpenClose");
-        assert_eq!(get_door_action("Door 3", model), "DoorRearOpenClose");
-        assert_eq!(get_door_action("Door 4", model), "DoorFourthOpenClose");
-        assert_eq!(get_door_action("Clearance", model), "ToggleDoorClearance");
-        assert_eq!(get_door_action("Unknown", model), "DoorFrontOpenClose");
+        let model = &VehicleProfile::default();
+        assert_eq!(get_door_action(DoorSelector::Door1, model), "DoorFrontOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door2, model), "DoorMiddleOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door3, model), "DoorRearOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Door4, model), "DoorFourthOpenClose");
+        assert_eq!(get_door_action(DoorSelector::Clearance, model), "ToggleDoorClearance");
+    }
+
+    #[test]
+    fn test_get_door_image() {
+        let theme = Theme::default();
+        let svg = get_door_image(&theme, DoorSelector::Door2, true).to_svg();
+        assert!(svg.contains(">2</text>"));
+        assert!(svg.contains(crate::render::COLOR_LAMP_ON));
+
+        let svg = get_door_image(&theme, DoorSelector::Clearance, false).to_svg();
+        assert!(!svg.contains("<text"));
     }
 }
 
-pub async fn handle_event_passenger_doors(
-    event: EventReceived,
-    config: &RequestConfig,
-    buttons: &mut HashMap<String, ActionInstance>,
-    client: &mut StreamDeckClient,
-) {
-    match event {
-        EventReceived::WillAppear(event) => {
-            if !buttons.contains_key(&event.context) {
-                let btn = ActionInstance {
-                    title: "".to_string(),
-                    uuid: event.action.clone(),
-                    state: 0, // state: event.payload.state.unwrap_or(0),
-                    value: 0,
-                    settings: event.payload.settings,
-                };
-                buttons.insert(event.context.clone(), btn);
-                client.transmitter.set_state(event.context.clone(), 0).await;
-
-                if let Some(button) = buttons.get_mut(&event.context) {
-                    let mut doorselector = get_value_or_empty(&button.settings, "DoorSelector");
-                    if doorselector.is_empty() {
-                        doorselector = "Door 1".to_string();
-                    }
-
-                    let mut image = "actions/assets/doorbutton_off.png";
-
-                    if doorselector == "Clearance" {
-                        image = "actions/assets/doorclearance_off.png";
-                    }
-
-                    let _ = client
-                        .transmitter
-                        .set_image(
-                            event.context.clone(),
-                            image.to_string(),
-                            StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                            None,
-                        )
-                        .await;
-                }
-            }
-        }
+fn get_door_lamp(doorselector: DoorSelector, state: &VehicleState) -> u8 {
+    if door_lamp(doorselector, state) { 1 } else { 0 }
+}
 
-        EventReceived::WillDisappear(event) => {
-            if buttons.contains_key(&event.context) {
-                buttons.remove(&event.context);
-            }
-        }
+/// Follows its lamp through `DoorLampChanged`, `telemetry` is only read when the key
+/// appears, gets another door or missed changes, to start with the current lamp.
+pub struct PassengerDoorsAction {
+    interlock: Interlock,
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+}
 
-        EventReceived::TitleParametersDidChange(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                if let Some(t) = event.payload.settings.get("title").and_then(|v| v.as_str()) {
-                    button.title = t.to_string();
-                }
-            }
-        }
+impl PassengerDoorsAction {
+    pub fn new(interlock: Interlock, telemetry: watch::Receiver<TelemetrySnapshot>) -> Self {
+        PassengerDoorsAction { interlock, telemetry }
+    }
+
+    fn read_lamp(&self, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<DoorSettings>().selector;
+        button.state = get_door_lamp(selector, &self.telemetry.borrow().vehicle_state);
+    }
+}
 
-        EventReceived::KeyDown(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let mut doorselector = get_value_or_empty(&button.settings, "DoorSelector");
-                if doorselector.is_empty() {
-                    doorselector = "Door 1".to_string();
-                }
+#[async_trait(?Send)]
+impl Action for PassengerDoorsAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
 
-                let d = get_door_action(doorselector.as_str(), &config.vehicle_model);
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<DoorSettings>(settings)
+    }
 
-                let cmd = format!("sendeventpress?event={}", d);
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_lamp(button);
+        self.render(ctx, button).await;
+    }
 
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
-            }
-        }
+    async fn on_did_receive_settings(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_lamp(button);
+        self.render(ctx, button).await;
+    }
 
-        EventReceived::KeyUp(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                let mut doorselector = get_value_or_empty(&button.settings, "DoorSelector");
-                if doorselector.is_empty() {
-                    doorselector = "Door 1".to_string();
-                }
+    async fn on_changes_missed(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.read_lamp(button);
+        self.render(ctx, button).await;
+    }
 
-                let d = get_door_action(doorselector.as_str(), &config.vehicle_model);
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<DoorSettings>().selector;
+        if !ctx.profile.has_door(selector) {
+            show_alert_for_button(ctx).await;
+            return;
+        }
+        // the key up still sends the release, so a press is never left hanging
+        if let Err(reason) = self.interlock.check_door(selector) {
+            logger(&format!("Interlock: {}", reason));
+            show_alert_for_button(ctx).await;
+            return;
+        }
+        let d = get_door_action(selector, ctx.profile);
 
-                let cmd = format!("sendeventrelease?event={}", d);
+        let cmd = format!("sendeventpress?event={}", d);
 
-                let _ = send_telemetry_bus_cmd(&config, &cmd).await;
-            }
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
+
+    async fn on_key_up(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<DoorSettings>().selector;
+        if !ctx.profile.has_door(selector) {
+            return;
         }
+        let d = get_door_action(selector, ctx.profile);
+
+        let cmd = format!("sendeventrelease?event={}", d);
 
-        EventReceived::DidReceiveSettings(event) => {
-            if let Some(button) = buttons.get_mut(&event.context) {
-                button.settings = event.payload.settings;
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
+
+    async fn on_state_change(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        change: &StateChange,
+    ) {
+        if let StateChange::DoorLampChanged { door, on } = *change {
+            if door == button.typed_settings::<DoorSettings>().selector {
+                button.state = if on { 1 } else { 0 };
+                self.render(ctx, button).await;
             }
         }
+    }
 
-        _ => {}
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let selector = button.typed_settings::<DoorSettings>().selector;
+        let mut image = get_door_image(ctx.theme, selector, button.state == 1);
+        if !ctx.profile.has_door(selector) {
+            image = image.unavailable();
+        }
+        set_image_for_button(ctx, image.to_data_url()).await;
     }
 }
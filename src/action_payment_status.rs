@@ -0,0 +1,173 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rusty_patio::streamdeck::generic::StreamDeckTarget;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::KeyImage;
+use super::settings::{parse_boxed_settings, PaymentDisplay, PaymentSettings};
+use super::telemetry::PaymentState;
+use super::{set_image_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.paymentstatus";
+
+fn format_money(amount: f64) -> String {
+    format!("{:.2} €", amount)
+}
+
+/// Title for the selected field, empty if no ticket is being sold.
+pub fn get_payment_title(display: PaymentDisplay, payment: Option<&PaymentState>) -> String {
+    let Some(payment) = payment else {
+        return "".to_string();
+    };
+    match display {
+        PaymentDisplay::Ticket => payment.ticket.clone(),
+        PaymentDisplay::PayMethod => payment.pay_method.clone(),
+        PaymentDisplay::PayMethodIcon => "".to_string(),
+        PaymentDisplay::Price => format_money(payment.price),
+        PaymentDisplay::Paid => format_money(payment.paid),
+        PaymentDisplay::Change => format_money(payment.change),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayMethod {
+    Cash,
+    Card,
+}
+
+impl PayMethod {
+    /// File name of the icon, see `Theme::icon`
+    pub fn icon(self) -> &'static str {
+        match self {
+            PayMethod::Cash => "coin.png",
+            PayMethod::Card => "payment_card.png",
+        }
+    }
+}
+
+/// The payment method to show an icon for, None for the default image of the action.
+pub fn get_payment_icon(payment: Option<&PaymentState>) -> Option<PayMethod> {
+    match payment.map(|p| p.pay_method.as_str()) {
+        Some("Cash") => Some(PayMethod::Cash),
+        Some("Card") => Some(PayMethod::Card),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment() -> PaymentState {
+        PaymentState {
+            ticket: "Single".to_string(),
+            pay_method: "Cash".to_string(),
+            price: 2.5,
+            paid: 5.0,
+            change: 2.5,
+        }
+    }
+
+    #[test]
+    fn test_get_payment_title() {
+        let payment = payment();
+        assert_eq!(get_payment_title(PaymentDisplay::Ticket, Some(&payment)), "Single");
+        assert_eq!(get_payment_title(PaymentDisplay::PayMethod, Some(&payment)), "Cash");
+        assert_eq!(get_payment_title(PaymentDisplay::Price, Some(&payment)), "2.50 €");
+        assert_eq!(get_payment_title(PaymentDisplay::Paid, Some(&payment)), "5.00 €");
+        assert_eq!(get_payment_title(PaymentDisplay::Change, Some(&payment)), "2.50 €");
+        assert_eq!(get_payment_title(PaymentDisplay::PayMethodIcon, Some(&payment)), "");
+        assert_eq!(get_payment_title(PaymentDisplay::Price, None), "");
+    }
+
+    #[test]
+    fn test_get_payment_icon() {
+        let mut payment = payment();
+        assert_eq!(get_payment_icon(Some(&payment)), Some(PayMethod::Cash));
+        payment.pay_method = "Card".to_string();
+        assert_eq!(get_payment_icon(Some(&payment)), Some(PayMethod::Card));
+        payment.pay_method = "Voucher".to_string();
+        assert_eq!(get_payment_icon(Some(&payment)), None);
+        assert_eq!(get_payment_icon(None), None);
+    }
+}
+
+pub struct PaymentStatusAction;
+
+#[async_trait(?Send)]
+impl Action for PaymentStatusAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<PaymentSettings>(settings)
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let display = button.typed_settings::<PaymentSettings>().display;
+
+        if display == PaymentDisplay::PayMethodIcon {
+            let method = get_payment_icon(telemetry.payment);
+            // button.value remembers the icon shown: 0 default, 1 cash, 2 card
+            let value = match method {
+                None => 0,
+                Some(PayMethod::Cash) => 1,
+                Some(PayMethod::Card) => 2,
+            };
+            if button.value != value {
+                button.value = value;
+                let image = match method {
+                    // embedded, icons of a user pack are outside of the plugin folder
+                    Some(method) => KeyImage::new().icon(&ctx.theme.icon(method.icon())).to_data_url(),
+                    None => "".to_string(),
+                };
+                set_image_for_button(ctx, image).await;
+            }
+            return;
+        }
+
+        let title = get_payment_title(display, telemetry.payment);
+        if button.shown_title != title {
+            button.shown_title = title.clone();
+            let _ = ctx
+                .client
+                .transmitter
+                .set_title(
+                    ctx.context.to_string(),
+                    title,
+                    StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                    None,
+                )
+                .await;
+        }
+    }
+
+    /// Clears the button, the next telemetry update draws the selected field.
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        set_image_for_button(ctx, "".to_string()).await;
+        let _ = ctx
+            .client
+            .transmitter
+            .set_title(
+                ctx.context.to_string(),
+                "".to_string(),
+                StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                None,
+            )
+            .await;
+        button.value = 0;
+        button.shown_title = "".to_string();
+    }
+}
@@ -0,0 +1,259 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use komsi::vehicle::VehicleState;
+use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+// Access items from the parent module (main.rs)
+use super::interlock::Interlock;
+use super::profiles::VehicleProfile;
+use super::registry::{Action, ActionContext};
+use super::settings::{
+    parse_boxed_settings, SequenceCondition, SequenceSettings, SequenceStep, TargetSettings,
+};
+use super::telemetry::TelemetrySnapshot;
+use super::{logger, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.sequence";
+
+pub fn is_condition_met(condition: SequenceCondition, state: &VehicleState) -> bool {
+    match condition {
+        SequenceCondition::Ignition(on) => state.ignition == on,
+        SequenceCondition::Engine(running) => state.engine == running,
+        SequenceCondition::FixingBrake(set) => state.fixing_brake == set,
+        SequenceCondition::Gear(gear) => state.gear_selector == gear.state(),
+    }
+}
+
+/// The command for a step that sends something to the game, None for the waiting steps.
+pub fn get_step_command(step: &SequenceStep, profile: &VehicleProfile) -> Option<String> {
+    match step {
+        SequenceStep::Event(event) => Some(format!("sendevent?event={}", profile.event(event))),
+        SequenceStep::Press(event) => Some(format!("sendeventpress?event={}", profile.event(event))),
+        SequenceStep::Release(event) => {
+            Some(format!("sendeventrelease?event={}", profile.event(event)))
+        }
+        SequenceStep::Cmd(cmd) => Some(cmd.clone()),
+        SequenceStep::Wait(_) | SequenceStep::Until(..) => None,
+    }
+}
+
+/// The event a step asks the game for, named as in the Lion's City, to check it
+/// with the interlocks. Releases are always allowed, like the key up of a door or gear key.
+pub fn get_step_event<'a>(step: &'a SequenceStep, profile: &'a VehicleProfile) -> Option<&'a str> {
+    match step {
+        SequenceStep::Event(event) | SequenceStep::Press(event) => Some(event.as_str()),
+        SequenceStep::Cmd(cmd) => {
+            let (request, params) = cmd.split_once('?')?;
+            let event = params.split('&').find_map(|param| param.strip_prefix("event="))?;
+            matches!(request, "sendevent" | "sendeventpress").then(|| profile.lions_city_event(event))
+        }
+        _ => None,
+    }
+}
+
+/// Runs all steps in order. Fails with a message for the log if a step is refused by
+/// the interlocks or could not be sent, if an `until` step times out or the telemetry task is gone.
+pub async fn run_sequence(
+    steps: Vec<SequenceStep>,
+    config: RequestConfig,
+    profile: VehicleProfile,
+    mut telemetry: watch::Receiver<TelemetrySnapshot>,
+    interlock: Interlock,
+) -> Result<(), String> {
+    for (i, step) in steps.iter().enumerate() {
+        if let Some(event) = get_step_event(step, &profile) {
+            interlock
+                .check_event(&profile, event)
+                .map_err(|reason| format!("step {} refused: {}", i + 1, reason))?;
+        }
+        if let Some(cmd) = get_step_command(step, &profile) {
+            if send_telemetry_bus_cmd(&config, &cmd).await.is_err() {
+                return Err(format!("step {} could not be sent: {}", i + 1, cmd));
+            }
+            continue;
+        }
+        match *step {
+            SequenceStep::Wait(delay) => tokio::time::sleep(delay).await,
+            SequenceStep::Until(condition, timeout) => {
+                let wait = async {
+                    while !is_condition_met(condition, &telemetry.borrow_and_update().vehicle_state) {
+                        if telemetry.changed().await.is_err() {
+                            return false;
+                        }
+                    }
+                    true
+                };
+                match tokio::time::timeout(timeout, wait).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        return Err("telemetry is not running".to_string());
+                    }
+                    Err(_) => {
+                        return Err(format!(
+                            "step {} timed out after {} ms: {:?}",
+                            i + 1,
+                            timeout.as_millis(),
+                            condition
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Gear;
+
+    #[test]
+    fn test_is_condition_met() {
+        let state = VehicleState {
+            ignition: true,
+            gear_selector: Gear::D.state(),
+            ..VehicleState::default()
+        };
+        assert!(is_condition_met(SequenceCondition::Ignition(true), &state));
+        assert!(!is_condition_met(SequenceCondition::Engine(true), &state));
+        assert!(is_condition_met(SequenceCondition::Engine(false), &state));
+        assert!(is_condition_met(SequenceCondition::Gear(Gear::D), &state));
+        assert!(!is_condition_met(SequenceCondition::Gear(Gear::R), &state));
+    }
+
+    #[test]
+    fn test_get_step_command() {
+        let profile = VehicleProfile::default();
+        assert_eq!(
+            get_step_command(&SequenceStep::Press("MotorStartStop".to_string()), &profile),
+            Some("sendeventpress?event=MotorStartStop".to_string())
+        );
+        assert_eq!(
+            get_step_command(&SequenceStep::Cmd("setbutton?button=Kneeling&state=1".to_string()), &profile),
+            Some("setbutton?button=Kneeling&state=1".to_string())
+        );
+        assert_eq!(
+            get_step_command(&SequenceStep::Wait(std::time::Duration::from_millis(10)), &profile),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_step_event() {
+        let profile = VehicleProfile::default();
+        let step = SequenceStep::Press("SetGearD".to_string());
+        assert_eq!(get_step_event(&step, &profile), Some("SetGearD"));
+        let step = SequenceStep::Release("SetGearD".to_string());
+        assert_eq!(get_step_event(&step, &profile), None);
+        let step = SequenceStep::Cmd("sendeventpress?event=ToggleDoorClearance".to_string());
+        assert_eq!(get_step_event(&step, &profile), Some("ToggleDoorClearance"));
+        let step = SequenceStep::Cmd("sendeventrelease?event=ToggleDoorClearance".to_string());
+        assert_eq!(get_step_event(&step, &profile), None);
+        let step = SequenceStep::Cmd("setbutton?button=Kneeling&state=1".to_string());
+        assert_eq!(get_step_event(&step, &profile), None);
+    }
+}
+
+/// Runs the steps from its settings when pressed, pressing it again while they run cancels them.
+///
+/// The sequence runs in its own task, so waiting for the bus never blocks the other keys.
+/// It is cancelled as well when its key disappears or the target changes.
+/// Failed sequences are reported on `failed` with the context of their key,
+/// the main loop shows the alert for them.
+pub struct SequenceAction {
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+    target: watch::Receiver<TargetSettings>,
+    interlock: Interlock,
+    failed: mpsc::UnboundedSender<(String, String)>,
+    running: RefCell<HashMap<String, JoinHandle<()>>>,
+}
+
+impl SequenceAction {
+    pub fn new(
+        telemetry: watch::Receiver<TelemetrySnapshot>,
+        target: watch::Receiver<TargetSettings>,
+        interlock: Interlock,
+        failed: mpsc::UnboundedSender<(String, String)>,
+    ) -> Self {
+        SequenceAction {
+            telemetry,
+            target,
+            interlock,
+            failed,
+            running: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Action for SequenceAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<SequenceSettings>(settings)
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let mut running = self.running.borrow_mut();
+        running.retain(|_, task| !task.is_finished());
+        if let Some(task) = running.remove(ctx.context) {
+            logger(&format!("Sequence {} cancelled", ctx.context));
+            task.abort();
+            return;
+        }
+
+        let steps = button.typed_settings::<SequenceSettings>().steps;
+        if steps.is_empty() {
+            return;
+        }
+        // the task outlives this event, so it gets its own config for the current target
+        let mut config = RequestConfig::new();
+        config.ip = ctx.config.ip.clone();
+        config.port = ctx.config.port;
+        config.vehicle_name = ctx.config.vehicle_name.clone();
+        let sequence = run_sequence(
+            steps,
+            config,
+            ctx.profile.clone(),
+            self.telemetry.clone(),
+            self.interlock.clone(),
+        );
+        let mut target = self.target.clone();
+        target.borrow_and_update();
+        let context = ctx.context.to_string();
+        let failed = self.failed.clone();
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                result = sequence => {
+                    if let Err(err) = result {
+                        let _ = failed.send((context, err));
+                    }
+                }
+                // the remaining steps would still go to the old target
+                _ = target.changed() => {
+                    logger(&format!("Sequence {} cancelled, the target has changed", context));
+                }
+            }
+        });
+        running.insert(ctx.context.to_string(), task);
+    }
+
+    async fn on_will_disappear(&self, ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {
+        if let Some(task) = self.running.borrow_mut().remove(ctx.context) {
+            logger(&format!("Sequence {} cancelled, its key is gone", ctx.context));
+            task.abort();
+        }
+    }
+}
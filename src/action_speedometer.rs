@@ -0,0 +1,118 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+// Access items from the parent module (main.rs)
+use super::gauge::{digital_gauge, needle_gauge};
+use super::registry::{Action, ActionContext, Telemetry};
+use super::render::svg_data_url;
+use super::settings::{parse_boxed_settings, GaugeStyle, SpeedUnit, SpeedometerSettings};
+use super::telemetry::TelemetrySnapshot;
+use super::{set_image_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.speedometer";
+
+/// End of the needle gauge scale: the max speed of the bus (in km/h, as the game reports it),
+/// or what city buses rarely go faster than if the game does not tell.
+fn get_scale_max(unit: SpeedUnit, max_speed: f64) -> f64 {
+    if max_speed > 0.0 {
+        return unit.convert_kmh(max_speed).round();
+    }
+    match unit {
+        SpeedUnit::Kmh => 100.0,
+        SpeedUnit::Mph => 60.0,
+    }
+}
+
+pub fn get_speedometer_svg(settings: &SpeedometerSettings, speed: i64, max_speed: f64) -> String {
+    match settings.style {
+        GaugeStyle::Needle => needle_gauge(
+            speed as f64,
+            get_scale_max(settings.unit, max_speed),
+            settings.unit.label(),
+        ),
+        GaugeStyle::Digital => digital_gauge(speed as f64, settings.unit.label()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_speedometer_svg() {
+        let settings = SpeedometerSettings {
+            style: GaugeStyle::Digital,
+            unit: SpeedUnit::Mph,
+        };
+        let image = get_speedometer_svg(&settings, 31, 0.0);
+        assert!(image.contains(">31</text>"));
+        assert!(image.contains(">mph</text>"));
+    }
+
+    #[test]
+    fn test_get_scale_max() {
+        assert_eq!(get_scale_max(SpeedUnit::Kmh, 80.0), 80.0);
+        assert_eq!(get_scale_max(SpeedUnit::Mph, 80.0), 50.0);
+        assert_eq!(get_scale_max(SpeedUnit::Kmh, 0.0), 100.0);
+        assert_eq!(get_scale_max(SpeedUnit::Mph, 0.0), 60.0);
+    }
+}
+
+/// Draws the gauge for the speed in `button.value`, the scale comes from the max speed
+/// in `telemetry`. A new bus is drawn again by the main loop, so the scale follows it.
+pub struct SpeedometerAction {
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+}
+
+impl SpeedometerAction {
+    pub fn new(telemetry: watch::Receiver<TelemetrySnapshot>) -> Self {
+        SpeedometerAction { telemetry }
+    }
+}
+
+#[async_trait(?Send)]
+impl Action for SpeedometerAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<SpeedometerSettings>(settings)
+    }
+
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.render(ctx, button).await;
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let unit = button.typed_settings::<SpeedometerSettings>().unit;
+        let speed = unit
+            .convert_kmh(telemetry.vehicle_state.speed as f64)
+            .abs()
+            .round() as i64;
+
+        // the websocket would be flooded with a new image on every tick otherwise
+        if button.value != speed {
+            button.value = speed;
+            self.render(ctx, button).await;
+        }
+    }
+
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let settings = button.typed_settings::<SpeedometerSettings>();
+        let max_speed = self.telemetry.borrow().vehicle_state.max_speed as f64;
+        let image = svg_data_url(&get_speedometer_svg(&settings, button.value, max_speed));
+        set_image_for_button(ctx, image).await;
+    }
+}
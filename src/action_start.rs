@@ -0,0 +1,152 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use rusty_patio::streamdeck::generic::StreamDeckTarget;
+use serde::de::Error;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext, Telemetry};
+use super::settings::{parse_settings, StartOption, StartSettings};
+use super::ActionInstance;
+
+pub const UUID: &str = "de.thatzok.thebus.startbutton";
+
+const START_SERVICE_EVENT: &str = "StartService";
+const STOP_SERVICE_EVENT: &str = "EndService";
+
+/// Game event sent on key press, None if the key only shows something.
+pub fn get_start_event(settings: &StartSettings) -> Option<String> {
+    let event = settings.event.trim();
+    if !event.is_empty() {
+        return Some(event.to_string());
+    }
+    match settings.option {
+        StartOption::Start => Some(START_SERVICE_EVENT.to_string()),
+        StartOption::Stop => Some(STOP_SERVICE_EVENT.to_string()),
+        _ => None,
+    }
+}
+
+/// Title for the selected option, empty for the options that only send an event.
+pub fn get_start_title(option: StartOption, telemetry: &Telemetry<'_>) -> String {
+    match option {
+        StartOption::BusName => telemetry.vehicle_model.to_string(),
+        StartOption::Start | StartOption::Stop | StartOption::Unsupported => "".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::ConnectionStatus;
+    use komsi::vehicle::VehicleState;
+
+    #[test]
+    fn test_get_start_event() {
+        let mut settings = StartSettings {
+            option: StartOption::Start,
+            event: "".to_string(),
+        };
+        assert_eq!(get_start_event(&settings), Some("StartService".to_string()));
+        settings.option = StartOption::Stop;
+        assert_eq!(get_start_event(&settings), Some("EndService".to_string()));
+        settings.option = StartOption::BusName;
+        assert_eq!(get_start_event(&settings), None);
+        settings.event = " NextLine ".to_string();
+        assert_eq!(get_start_event(&settings), Some("NextLine".to_string()));
+    }
+
+    #[test]
+    fn test_get_start_title() {
+        let vehicle_state = VehicleState::default();
+        let buttons = HashMap::new();
+        let lights = HashMap::new();
+        let telemetry = Telemetry {
+            in_bus: true,
+            status: ConnectionStatus::Connected,
+            vehicle_model: "MAN Lion's City",
+            latency: None,
+            vehicle_state: &vehicle_state,
+            buttons: &buttons,
+            lights: &lights,
+            payment: None,
+            blink: false,
+        };
+
+        assert_eq!(get_start_title(StartOption::BusName, &telemetry), "MAN Lion's City");
+        assert_eq!(get_start_title(StartOption::Start, &telemetry), "");
+        assert_eq!(get_start_title(StartOption::Unsupported, &telemetry), "");
+    }
+}
+
+pub struct StartAction;
+
+#[async_trait(?Send)]
+impl Action for StartAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        let settings = parse_settings::<StartSettings>(settings)?;
+        settings.validate().map_err(serde_json::Error::custom)?;
+        Ok(Box::new(settings))
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        if let Some(event) = get_start_event(&button.typed_settings::<StartSettings>()) {
+            let cmd = format!("sendevent?event={}", event);
+            let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+        }
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let option = button.typed_settings::<StartSettings>().option;
+        if option == StartOption::Start || option == StartOption::Stop {
+            return;
+        }
+        let title = get_start_title(option, telemetry);
+        if button.shown_title != title {
+            button.shown_title = title.clone();
+            let _ = ctx
+                .client
+                .transmitter
+                .set_title(
+                    ctx.context.to_string(),
+                    title,
+                    StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                    None,
+                )
+                .await;
+        }
+    }
+
+    /// Clears the title, the next telemetry update draws the one of the (new) option.
+    async fn render(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        let option = button.typed_settings::<StartSettings>().option;
+        if option == StartOption::Start || option == StartOption::Stop {
+            return;
+        }
+        let _ = ctx
+            .client
+            .transmitter
+            .set_title(
+                ctx.context.to_string(),
+                "".to_string(),
+                StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+                None,
+            )
+            .await;
+        button.shown_title = "".to_string();
+    }
+}
@@ -0,0 +1,75 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use the_bus_telemetry::api::send_telemetry_bus_cmd;
+
+// Access items from the parent module (main.rs)
+use super::registry::{Action, ActionContext, Telemetry};
+use super::settings::{parse_boxed_settings, StopRequestSettings};
+use super::{set_state_for_button, ActionInstance};
+
+pub const UUID: &str = "de.thatzok.thebus.stoprequest";
+
+/// State of the key: 1 while a stop is requested, flashing with `blink` until the doors open if `flash` is set.
+pub fn get_stop_request_state(requested: bool, doors_open: bool, flash: bool, blink: bool) -> u8 {
+    if !requested || (flash && !doors_open && !blink) {
+        0
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_stop_request_state() {
+        assert_eq!(get_stop_request_state(false, false, false, true), 0);
+        assert_eq!(get_stop_request_state(true, false, false, false), 1);
+
+        // flashes while the doors are closed
+        assert_eq!(get_stop_request_state(true, false, true, true), 1);
+        assert_eq!(get_stop_request_state(true, false, true, false), 0);
+
+        // steady once the doors are open
+        assert_eq!(get_stop_request_state(true, true, true, false), 1);
+    }
+}
+
+pub struct StopRequestAction;
+
+#[async_trait(?Send)]
+impl Action for StopRequestAction {
+    fn uuid(&self) -> &'static str {
+        UUID
+    }
+
+    fn parse_settings(
+        &self,
+        settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        parse_boxed_settings::<StopRequestSettings>(settings)
+    }
+
+    async fn on_key_down(&self, ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {
+        let cmd = format!("sendevent?event={}", ctx.profile.event("ResetStopRequest"));
+        let _ = send_telemetry_bus_cmd(ctx.config, &cmd).await;
+    }
+
+    async fn on_telemetry_update(
+        &self,
+        ctx: &mut ActionContext<'_>,
+        button: &mut ActionInstance,
+        telemetry: &Telemetry<'_>,
+    ) {
+        let state = get_stop_request_state(
+            telemetry.vehicle_state.lights_stop_request,
+            telemetry.vehicle_state.doors,
+            button.typed_settings::<StopRequestSettings>().flash,
+            telemetry.blink,
+        );
+        set_state_for_button(ctx, button, state).await;
+    }
+}
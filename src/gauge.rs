@@ -0,0 +1,137 @@
+//! Key images drawn as SVG, for values that change too often for a PNG per value.
+//! Send them with `render::svg_data_url`.
+
+use super::render::KEY_SIZE;
+
+/// Start of the needle gauge scale, in degrees clockwise from 3 o'clock.
+const NEEDLE_START: f64 = 135.0;
+/// The scale covers three quarters of a circle.
+const NEEDLE_SWEEP: f64 = 270.0;
+
+fn point_on_circle(radius: f64, degrees: f64) -> (f64, f64) {
+    let radians = degrees.to_radians();
+    let center = f64::from(KEY_SIZE) / 2.0;
+    (
+        center + radius * radians.cos(),
+        center + radius * radians.sin(),
+    )
+}
+
+/// A round gauge with a needle for `value` between 0 and `max`, the value as text below.
+pub fn needle_gauge(value: f64, max: f64, unit: &str) -> String {
+    let fraction = if max > 0.0 { (value / max).clamp(0.0, 1.0) } else { 0.0 };
+    let radius = 56.0;
+
+    let (start_x, start_y) = point_on_circle(radius, NEEDLE_START);
+    let (end_x, end_y) = point_on_circle(radius, NEEDLE_START + NEEDLE_SWEEP);
+    let (needle_x, needle_y) = point_on_circle(radius - 8.0, NEEDLE_START + NEEDLE_SWEEP * fraction);
+
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="144" height="144" viewBox="0 0 144 144">"##,
+            r##"<rect width="144" height="144" fill="#000000"/>"##,
+            r##"<path d="M {:.1} {:.1} A {r} {r} 0 1 1 {:.1} {:.1}" fill="none" stroke="#555555" stroke-width="8"/>"##,
+            r##"<line x1="72" y1="72" x2="{:.1}" y2="{:.1}" stroke="#ff3b30" stroke-width="5" stroke-linecap="round"/>"##,
+            r##"<circle cx="72" cy="72" r="7" fill="#ffffff"/>"##,
+            r##"<text x="72" y="116" font-family="sans-serif" font-size="26" font-weight="bold" fill="#ffffff" text-anchor="middle">{}</text>"##,
+            r##"<text x="72" y="136" font-family="sans-serif" font-size="14" fill="#aaaaaa" text-anchor="middle">{}</text>"##,
+            "</svg>"
+        ),
+        start_x,
+        start_y,
+        end_x,
+        end_y,
+        needle_x,
+        needle_y,
+        value.round(),
+        unit,
+        r = radius,
+    )
+}
+
+/// Just the value in big digits with the unit below.
+pub fn digital_gauge(value: f64, unit: &str) -> String {
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="144" height="144" viewBox="0 0 144 144">"##,
+            r##"<rect width="144" height="144" fill="#000000"/>"##,
+            r##"<text x="72" y="88" font-family="sans-serif" font-size="56" font-weight="bold" fill="#ffffff" text-anchor="middle">{}</text>"##,
+            r##"<text x="72" y="124" font-family="sans-serif" font-size="20" fill="#aaaaaa" text-anchor="middle">{}</text>"##,
+            "</svg>"
+        ),
+        value.round(),
+        unit,
+    )
+}
+
+/// A vertical bar filled to `percent`, with a label above it.
+/// `alarm` draws the bar in red.
+pub fn bar_gauge(percent: f64, label: &str, alarm: bool) -> String {
+    let fraction = (percent / 100.0).clamp(0.0, 1.0);
+    let bar_height = 96.0;
+    // the fill keeps 4 px distance to the frame
+    let fill_height = (bar_height - 8.0) * fraction;
+    let color = if alarm {
+        "#ff3b30"
+    } else if fraction < 0.25 {
+        "#ffcc00"
+    } else {
+        "#34c759"
+    };
+
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="144" height="144" viewBox="0 0 144 144">"##,
+            r##"<rect width="144" height="144" fill="#000000"/>"##,
+            r##"<text x="72" y="26" font-family="sans-serif" font-size="18" fill="#aaaaaa" text-anchor="middle">{}</text>"##,
+            r##"<rect x="48" y="36" width="48" height="{:.1}" fill="none" stroke="#555555" stroke-width="4"/>"##,
+            r##"<rect x="52" y="{:.1}" width="40" height="{:.1}" fill="{}"/>"##,
+            "</svg>"
+        ),
+        label,
+        bar_height,
+        36.0 + bar_height - 4.0 - fill_height,
+        fill_height,
+        color,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needle_gauge() {
+        let empty = needle_gauge(0.0, 120.0, "km/h");
+        // needle points to the start of the scale
+        assert!(empty.contains(r#"x2="38.1" y2="105.9""#));
+        assert!(empty.contains(">0</text>"));
+
+        let full = needle_gauge(200.0, 120.0, "km/h");
+        assert!(full.contains(r#"x2="105.9" y2="105.9""#));
+        assert!(full.contains(">200</text>"));
+
+        // same value, same image
+        assert_eq!(needle_gauge(50.0, 120.0, "mph"), needle_gauge(50.0, 120.0, "mph"));
+    }
+
+    #[test]
+    fn test_bar_gauge() {
+        let full = bar_gauge(100.0, "Fuel", false);
+        assert!(full.contains(r##"y="40.0" width="40" height="88.0" fill="#34c759""##));
+
+        let empty = bar_gauge(0.0, "Battery", false);
+        assert!(empty.contains(r#"y="128.0" width="40" height="0.0""#));
+        assert!(empty.contains(">Battery</text>"));
+
+        assert!(bar_gauge(10.0, "Fuel", false).contains("#ffcc00"));
+        assert!(bar_gauge(10.0, "Fuel", true).contains("#ff3b30"));
+    }
+
+    #[test]
+    fn test_digital_gauge() {
+        let image = digital_gauge(49.6, "km/h");
+        assert!(image.contains(">50</text>"));
+        assert!(image.contains(">km/h</text>"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Tells a tap from a hold and a double tap, for keys that have events for them.
+//!
+//! Keys without a hold or double tap event never get here, they react on key down at once.
+//! A tap of a key with gestures is only known on key up (or, with a double tap event,
+//! when no second press followed), so its action sees key down and key up together then.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::settings::GestureSettings;
+
+/// how long after a tap a second press counts as double tap
+pub const DOUBLE_TAP_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    Hold,
+    DoubleTap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyState {
+    /// pressed at the instant, a hold if still pressed at the deadline
+    Down { deadline: Option<Instant> },
+    /// released after a tap, waiting for a second press until the deadline
+    Released { deadline: Instant },
+    /// the gesture is done, the key up that follows is ignored
+    Done,
+}
+
+/// State of every key that is in the middle of a gesture.
+#[derive(Debug, Default)]
+pub struct GestureDetector {
+    keys: HashMap<String, KeyState>,
+}
+
+impl GestureDetector {
+    pub fn key_down(&mut self, context: &str, settings: &GestureSettings, now: Instant) -> Option<Gesture> {
+        match self.keys.get(context) {
+            Some(KeyState::Released { .. }) => {
+                self.keys.insert(context.to_string(), KeyState::Done);
+                Some(Gesture::DoubleTap)
+            }
+            _ => {
+                let deadline = (!settings.hold.is_empty()).then(|| now + settings.hold_time);
+                self.keys.insert(context.to_string(), KeyState::Down { deadline });
+                None
+            }
+        }
+    }
+
+    pub fn key_up(&mut self, context: &str, settings: &GestureSettings, now: Instant) -> Option<Gesture> {
+        match self.keys.remove(context) {
+            Some(KeyState::Down { .. }) if !settings.double_tap.is_empty() => {
+                let deadline = now + DOUBLE_TAP_TIME;
+                self.keys.insert(context.to_string(), KeyState::Released { deadline });
+                None
+            }
+            Some(KeyState::Down { .. }) => Some(Gesture::Tap),
+            _ => None,
+        }
+    }
+
+    /// The next instant `expire` has something to do, None if no key is waiting.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.keys
+            .values()
+            .filter_map(|state| match state {
+                KeyState::Down { deadline } => *deadline,
+                KeyState::Released { deadline } => Some(*deadline),
+                KeyState::Done => None,
+            })
+            .min()
+    }
+
+    /// Gestures that are complete at `now`: keys held long enough and taps without a second press.
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, Gesture)> {
+        let mut gestures = Vec::new();
+        self.keys.retain(|context, state| match *state {
+            KeyState::Down { deadline: Some(deadline) } if deadline <= now => {
+                gestures.push((context.clone(), Gesture::Hold));
+                *state = KeyState::Done;
+                true
+            }
+            KeyState::Released { deadline } if deadline <= now => {
+                gestures.push((context.clone(), Gesture::Tap));
+                false
+            }
+            _ => true,
+        });
+        gestures
+    }
+
+    /// Forgets a key that is gone, e.g. on a page switch.
+    pub fn remove(&mut self, context: &str) {
+        self.keys.remove(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::DEFAULT_HOLD_TIME;
+
+    fn gestures(hold: &str, double_tap: &str) -> GestureSettings {
+        GestureSettings {
+            hold: hold.to_string(),
+            double_tap: double_tap.to_string(),
+            ..GestureSettings::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tap_and_hold() {
+        let settings = gestures("ToggleDoorClearance", "");
+        let mut detector = GestureDetector::default();
+
+        assert_eq!(detector.key_down("k1", &settings, Instant::now()), None);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(detector.key_up("k1", &settings, Instant::now()), Some(Gesture::Tap));
+        assert_eq!(detector.next_deadline(), None);
+
+        detector.key_down("k1", &settings, Instant::now());
+        assert_eq!(detector.next_deadline(), Some(Instant::now() + DEFAULT_HOLD_TIME));
+        tokio::time::advance(DEFAULT_HOLD_TIME).await;
+        assert_eq!(detector.expire(Instant::now()), vec![("k1".to_string(), Gesture::Hold)]);
+        // releasing after a hold is no tap
+        assert_eq!(detector.key_up("k1", &settings, Instant::now()), None);
+        assert!(detector.expire(Instant::now()).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_double_tap() {
+        let settings = gestures("", "ToggleWarningLights");
+        let mut detector = GestureDetector::default();
+
+        detector.key_down("k1", &settings, Instant::now());
+        assert_eq!(detector.key_up("k1", &settings, Instant::now()), None);
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(detector.key_down("k1", &settings, Instant::now()), Some(Gesture::DoubleTap));
+        assert_eq!(detector.key_up("k1", &settings, Instant::now()), None);
+
+        // a single tap is known once the second press did not come
+        detector.key_down("k1", &settings, Instant::now());
+        detector.key_up("k1", &settings, Instant::now());
+        tokio::time::advance(DOUBLE_TAP_TIME - Duration::from_millis(1)).await;
+        assert!(detector.expire(Instant::now()).is_empty());
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(detector.expire(Instant::now()), vec![("k1".to_string(), Gesture::Tap)]);
+        assert_eq!(detector.next_deadline(), None);
+    }
+}
@@ -0,0 +1,236 @@
+//! Refuses door and gear commands a real bus would not allow, e.g. opening the doors at 50 km/h.
+//!
+//! The rules are switched on in the global settings. A refused key shows an alert
+//! and the reason is logged, nothing is sent to the game. Events given by name (sequences,
+//! hold and double tap events) go through the same rules via `check_event`.
+
+use komsi::vehicle::VehicleState;
+use tokio::sync::watch;
+
+use super::profiles::VehicleProfile;
+use super::settings::{DoorSelector, Gear, InterlockSettings, LightSelector};
+use super::state_diff::door_lamp;
+use super::telemetry::TelemetrySnapshot;
+
+fn is_standing(settings: &InterlockSettings, state: &VehicleState) -> bool {
+    (state.speed as f64).abs() <= settings.max_speed as f64
+}
+
+/// Err with the reason if `door` must not be opened now. Closing is always allowed.
+pub fn check_door(settings: &InterlockSettings, state: &VehicleState, door: DoorSelector) -> Result<(), String> {
+    // the lamp is lit while the door is open
+    if door_lamp(door, state) {
+        return Ok(());
+    }
+    if settings.doors && !is_standing(settings, state) {
+        return Err(format!("{:?} refused at {} km/h", door, state.speed));
+    }
+    if settings.door_brake && !state.fixing_brake && !state.lights_stop_brake {
+        return Err(format!("{:?} refused without parking brake or bus stop brake", door));
+    }
+    Ok(())
+}
+
+/// Err with the reason if `gear` must not be selected now. N is always allowed.
+pub fn check_gear(settings: &InterlockSettings, state: &VehicleState, gear: Gear) -> Result<(), String> {
+    if !settings.gears || gear == Gear::N || state.gear_selector == gear.state() {
+        return Ok(());
+    }
+    if !is_standing(settings, state) {
+        return Err(format!("Gear {} refused at {} km/h", gear.letter(), state.speed));
+    }
+    Ok(())
+}
+
+/// What an event moves, for the events the interlocks or the vehicle profiles care about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTarget {
+    Door(DoorSelector),
+    Gear(Gear),
+    Light(LightSelector),
+}
+
+/// What `event` (named as in the Lion's City, see `VehicleProfile::event`) moves,
+/// None for everything else.
+pub fn get_event_target(event: &str) -> Option<EventTarget> {
+    let target = match event {
+        "DoorFrontOpenClose" => EventTarget::Door(DoorSelector::Door1),
+        "DoorMiddleOpenClose" => EventTarget::Door(DoorSelector::Door2),
+        "DoorRearOpenClose" => EventTarget::Door(DoorSelector::Door3),
+        "DoorFourthOpenClose" => EventTarget::Door(DoorSelector::Door4),
+        "ToggleDoorClearance" => EventTarget::Door(DoorSelector::Clearance),
+        "SetGearD" => EventTarget::Gear(Gear::D),
+        "SetGearN" => EventTarget::Gear(Gear::N),
+        "SetGearR" => EventTarget::Gear(Gear::R),
+        "ToggleMainLight" => EventTarget::Light(LightSelector::MainLight),
+        "ToggleHighBeam" => EventTarget::Light(LightSelector::HighBeam),
+        "ToggleFogLight" => EventTarget::Light(LightSelector::FogLight),
+        "ToggleInteriorLight" => EventTarget::Light(LightSelector::InteriorLight),
+        _ => return None,
+    };
+    Some(target)
+}
+
+/// Err with the reason if `event` must not be sent now: the bus does not have
+/// the door, gear or light it moves, or the rules refuse it like the key for it.
+pub fn check_event(
+    settings: &InterlockSettings,
+    state: &VehicleState,
+    profile: &VehicleProfile,
+    event: &str,
+) -> Result<(), String> {
+    match get_event_target(event) {
+        Some(EventTarget::Door(door)) if !profile.has_door(door) => {
+            Err(format!("{} refused, the bus has no {:?}", event, door))
+        }
+        Some(EventTarget::Door(door)) => check_door(settings, state, door),
+        Some(EventTarget::Gear(gear)) if !profile.has_gear(gear) => {
+            Err(format!("{} refused, the bus has no gear {}", event, gear.letter()))
+        }
+        Some(EventTarget::Gear(gear)) => check_gear(settings, state, gear),
+        Some(EventTarget::Light(light)) if !profile.has_light(light) => {
+            Err(format!("{} refused, the bus has no {:?}", event, light))
+        }
+        Some(EventTarget::Light(_)) | None => Ok(()),
+    }
+}
+
+/// The rules from the global settings together with the latest telemetry,
+/// handed to the actions that need to ask before sending.
+#[derive(Clone)]
+pub struct Interlock {
+    settings: watch::Receiver<InterlockSettings>,
+    telemetry: watch::Receiver<TelemetrySnapshot>,
+}
+
+impl Interlock {
+    pub fn new(
+        settings: watch::Receiver<InterlockSettings>,
+        telemetry: watch::Receiver<TelemetrySnapshot>,
+    ) -> Self {
+        Interlock { settings, telemetry }
+    }
+
+    /// No rules, e.g. for tests.
+    pub fn disabled() -> Self {
+        let (_, settings) = watch::channel(InterlockSettings::default());
+        let (_, telemetry) = watch::channel(TelemetrySnapshot::default());
+        Interlock { settings, telemetry }
+    }
+
+    pub fn check_door(&self, door: DoorSelector) -> Result<(), String> {
+        check_door(&self.settings.borrow(), &self.telemetry.borrow().vehicle_state, door)
+    }
+
+    pub fn check_gear(&self, gear: Gear) -> Result<(), String> {
+        check_gear(&self.settings.borrow(), &self.telemetry.borrow().vehicle_state, gear)
+    }
+
+    pub fn check_event(&self, profile: &VehicleProfile, event: &str) -> Result<(), String> {
+        check_event(
+            &self.settings.borrow(),
+            &self.telemetry.borrow().vehicle_state,
+            profile,
+            event,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> InterlockSettings {
+        InterlockSettings {
+            doors: true,
+            door_brake: true,
+            gears: true,
+            ..InterlockSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_check_door() {
+        let mut state = VehicleState {
+            speed: 50,
+            ..VehicleState::default()
+        };
+        assert!(check_door(&rules(), &state, DoorSelector::Door1).is_err());
+        // without rules everything goes
+        assert!(check_door(&InterlockSettings::default(), &state, DoorSelector::Door1).is_ok());
+
+        // closing an open door is fine at any speed
+        state.lights_front_door = true;
+        assert!(check_door(&rules(), &state, DoorSelector::Door1).is_ok());
+
+        let mut state = VehicleState {
+            speed: 2,
+            ..VehicleState::default()
+        };
+        assert!(check_door(&rules(), &state, DoorSelector::Clearance).unwrap_err().contains("brake"));
+        state.lights_stop_brake = true;
+        assert!(check_door(&rules(), &state, DoorSelector::Clearance).is_ok());
+    }
+
+    #[test]
+    fn test_check_gear() {
+        let state = VehicleState {
+            speed: 30,
+            gear_selector: Gear::D.state(),
+            ..VehicleState::default()
+        };
+        assert!(check_gear(&rules(), &state, Gear::R).is_err());
+        assert!(check_gear(&rules(), &state, Gear::N).is_ok());
+        assert!(check_gear(&rules(), &state, Gear::D).is_ok());
+
+        let state = VehicleState {
+            gear_selector: Gear::D.state(),
+            ..VehicleState::default()
+        };
+        assert!(check_gear(&rules(), &state, Gear::R).is_ok());
+    }
+
+    #[test]
+    fn test_check_event() {
+        let state = VehicleState {
+            speed: 50,
+            ..VehicleState::default()
+        };
+        let profile = VehicleProfile::default();
+        assert!(check_event(&rules(), &state, &profile, "ToggleDoorClearance").is_err());
+        assert!(check_event(&rules(), &state, &profile, "SetGearR").is_err());
+        assert!(check_event(&rules(), &state, &profile, "Kneeling").is_ok());
+        // the Lion's City has three doors
+        let no_door = check_event(&InterlockSettings::default(), &state, &profile, "DoorFourthOpenClose");
+        assert!(no_door.unwrap_err().contains("Door4"));
+    }
+
+    #[test]
+    fn test_event_targets_match_the_keys() {
+        use crate::action_lights::get_light_event;
+        use crate::action_passenger_doors::get_door_action;
+
+        let profile = VehicleProfile::default();
+        for door in [
+            DoorSelector::Door1,
+            DoorSelector::Door2,
+            DoorSelector::Door3,
+            DoorSelector::Door4,
+            DoorSelector::Clearance,
+        ] {
+            assert_eq!(get_event_target(&get_door_action(door, &profile)), Some(EventTarget::Door(door)));
+        }
+        for light in [
+            LightSelector::MainLight,
+            LightSelector::HighBeam,
+            LightSelector::FogLight,
+            LightSelector::InteriorLight,
+        ] {
+            assert_eq!(get_event_target(get_light_event(light)), Some(EventTarget::Light(light)));
+        }
+        for gear in [Gear::D, Gear::N, Gear::R] {
+            let event = format!("SetGear{}", gear.letter());
+            assert_eq!(get_event_target(&event), Some(EventTarget::Gear(gear)));
+        }
+    }
+}
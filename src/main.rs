@@ -1,55 +1,96 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 
 use std::io::Write;
-use std::time::Duration;
+use std::sync::Arc;
 
 use rusty_patio::{
     streamdeck::{
-        arguments::StreamDeckArgs, client::StreamDeckClient, events::event_received::EventReceived,
+        arguments::StreamDeckArgs, events::event_received::EventReceived,
         generic::StreamDeckTarget,
     },
     websocket::connect_streamdeck,
 };
 
-use crate::action_fixing_brake::handle_event_fixing_brake;
-use crate::action_fixing_gearselect::handle_event_fixing_gearselect;
-use crate::action_ignition::handle_event_ignition;
-use crate::action_inbus::handle_event_inbus;
-use crate::action_indicators::{
-    get_indicator_image_off, get_indicator_image_on, handle_event_indicators,
+use the_bus_telemetry::api::RequestConfig;
+use crate::profiles::{get_deck_profile, get_profile_devices, ProfileDatabase, VehicleProfile};
+use crate::registry::{ActionContext, ActionRegistry, Telemetry};
+use crate::interlock::Interlock;
+use crate::settings::{
+    parse_settings, target_from_settings, GestureSettings, GlobalSettings, InterlockSettings,
+    ProfileSwitchSettings, TargetSettings,
 };
-use crate::action_passenger_doors::handle_event_passenger_doors;
-use crate::action_stop_brake::handle_event_stop_brake;
-use the_bus_telemetry::api::{get_current_vehicle_name, get_vehicle, RequestConfig};
-use the_bus_telemetry::api2vehicle::get_vehicle_state_from_api;
-use komsi::vehicle::VehicleState;
-use crate::action_change::handle_event_change;
+use crate::state_diff::STATE_CHANGE_CAPACITY;
+use crate::telemetry::{apply_target, spawn_telemetry_task, InfoRequests, TelemetrySnapshot};
+use crate::theme::Theme;
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 
 mod action_fixing_brake;
+mod action_fuel;
 mod action_fixing_gearselect;
 mod action_ignition;
 mod action_inbus;
 mod action_indicators;
+mod action_lights;
 mod action_passenger_doors;
+mod action_sequence;
+mod action_speedometer;
+mod action_start;
 mod action_stop_brake;
+mod action_stop_request;
 mod action_change;
-
-const UUID_FIXING_BRAKE: &str = "de.thatzok.thebus.fixingbrake";
-const UUID_INBUS: &str = "de.thatzok.thebus.inbus";
-const UUID_GEARSELECT: &str = "de.thatzok.thebus.gearselect";
-const UUID_IGNITION: &str = "de.thatzok.thebus.ignition";
-const UUID_PASSENGER_DOORS: &str = "de.thatzok.thebus.dooraction";
-const UUID_INDICATORS: &str = "de.thatzok.thebus.indicatorcontrol";
-const UUID_STOP_BRAKE: &str = "de.thatzok.thebus.stopbrake";
-const UUID_CHANGE: &str = "de.thatzok.thebus.changeaction";
+mod action_connection_status;
+mod action_custom;
+mod action_custom_button;
+mod action_dial;
+mod gauge;
+mod gesture;
+mod interlock;
+mod profiles;
+mod action_payment_status;
+mod registry;
+mod render;
+mod settings;
+mod state_diff;
+mod telemetry;
+mod theme;
 
 struct ActionInstance {
     title: String,
+    // the title the plugin drew last, `title` is the one the user set
+    shown_title: String,
     uuid: String,
     state: u8,
     value: i64,
     settings: HashMap<String, serde_json::Value>,
+    // typed settings as returned by `Action::parse_settings`
+    parsed_settings: Box<dyn Any>,
+    // hold and double tap events, the same for every action
+    gestures: GestureSettings,
+}
+
+impl ActionInstance {
+    fn new(uuid: &str, settings: HashMap<String, serde_json::Value>) -> Self {
+        ActionInstance {
+            title: "".to_string(),
+            shown_title: "".to_string(),
+            uuid: uuid.to_string(),
+            state: 0,
+            value: 0,
+            settings,
+            parsed_settings: Box::new(()),
+            gestures: GestureSettings::default(),
+        }
+    }
+
+    /// The parsed settings of this button, or the defaults if they have not been parsed (yet).
+    fn typed_settings<T: Clone + Default + 'static>(&self) -> T {
+        self.parsed_settings
+            .downcast_ref::<T>()
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 fn describe_event(event: &EventReceived) -> String {
@@ -130,250 +171,144 @@ fn logger(msg: &str) {
 
     writeln!(log_file, "{}", msg);
 }
-fn get_value_or_empty(map: &HashMap<String, serde_json::Value>, key: &str) -> String {
-    let bla = map.get(key).map(|s| s.clone());
-    if bla.is_none() {
-        return "".to_string();
-    }
-    let fasel: serde_json::Value = bla.unwrap();
-    let ret = fasel.as_str().unwrap_or("");
-    ret.to_string()
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
-
-    #[test]
-    fn test_get_value_or_empty() {
-        let mut map = HashMap::new();
-        map.insert("key1".to_string(), json!("value1"));
-        map.insert("key2".to_string(), json!(123));
+async fn set_title_for_button(ctx: &mut ActionContext<'_>, title: String) {
+    let _ = ctx
+        .client
+        .transmitter
+        .set_title(
+            ctx.context.to_string(),
+            title,
+            StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+            None,
+        )
+        .await;
+}
 
-        assert_eq!(get_value_or_empty(&map, "key1"), "value1");
-        assert_eq!(get_value_or_empty(&map, "key2"), ""); // json!(123) is not a string
-        assert_eq!(get_value_or_empty(&map, "key3"), ""); // non-existent key
+async fn set_state_for_button(ctx: &mut ActionContext<'_>, button: &mut ActionInstance, state: u8) {
+    if button.state != state {
+        button.state = state;
+        ctx.client
+            .transmitter
+            .set_state(ctx.context.to_string(), button.state)
+            .await
     }
 }
 
-async fn set_value_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    value: i64,
-    client: &mut StreamDeckClient,
-) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            if btn.value != value {
-                btn.value = value;
-                client
-                    .transmitter
-                    .set_title(
-                        context.clone(),
-                        btn.value.to_string() + "%",
-                        StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                        None,
-                    )
-                    .await
-                    .unwrap();
-            }
-        }
-    }
+async fn set_image_for_button(ctx: &mut ActionContext<'_>, image: String) {
+    let _ = ctx
+        .client
+        .transmitter
+        .set_image(
+            ctx.context.to_string(),
+            image,
+            StreamDeckTarget::HARDWARE_AND_SOFTWARE,
+            None,
+        )
+        .await;
 }
 
-async fn set_state_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    state: u8,
-    client: &mut StreamDeckClient,
-) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            if btn.state != state {
-                btn.state = state;
-                client
-                    .transmitter
-                    .set_state(context.clone(), btn.state)
-                    .await
-            }
-        }
-    }
+/// Shows the alert triangle on the key, e.g. for a door the bus does not have.
+async fn show_alert_for_button(ctx: &mut ActionContext<'_>) {
+    ctx.client
+        .transmitter
+        .show_alert(ctx.context.to_string())
+        .await;
 }
 
-async fn set_title_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    value: String,
-    client: &mut StreamDeckClient,
+/// Shows values on the touch strip of a Stream Deck+ (see the layout in manifest.json).
+async fn set_feedback_for_button(ctx: &mut ActionContext<'_>, feedback: serde_json::Value) {
+    ctx.client
+        .transmitter
+        .set_feedback(ctx.context.to_string(), feedback)
+        .await;
+}
+
+/// Sends all telemetry requests and commands to a new target, if it has changed.
+fn update_target(
+    config: &mut RequestConfig,
+    target_tx: &watch::Sender<TargetSettings>,
+    target: TargetSettings,
 ) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            client
-                .transmitter
-                .set_title(
-                    context.clone(),
-                    value.clone(),
-                    StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                    None,
-                )
-                .await
-                .unwrap();
-        }
+    if *target_tx.borrow() != target {
+        logger(&format!("Telemetry target is now {}:{}", target.ip, target.port));
+        apply_target(config, &target);
+        let _ = target_tx.send(target);
     }
 }
-async fn set_gearselect_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    state: u8,
-    client: &mut StreamDeckClient,
-) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            if btn.state != state {
-                btn.state = state;
-                let mut gear = get_value_or_empty(&btn.settings, "GearSelection");
-                if gear.is_empty() {
-                    gear = "2".to_string();
-                }
 
-                let mut active = "off";
-                if btn.state.to_string() == gear {
-                    active = "on";
-                } else {
-                    active = "off";
-                }
+/// The `-info` argument the Stream Deck software starts the plugin with, empty if missing.
+fn get_info_argument() -> String {
+    let mut args = std::env::args().skip_while(|arg| arg != "-info");
+    args.nth(1).unwrap_or_default()
+}
 
-                let g = match gear.as_str() {
-                    "1" => "D",
-                    "2" => "N",
-                    "3" => "R",
-                    _ => "N",
-                };
-
-                let image = format!("actions/assets/gear_{}_{}.png", g, active);
-
-                let _ = client
-                    .transmitter
-                    .set_image(
-                        context.clone(),
-                        image,
-                        StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                        None,
-                    )
-                    .await;
-            }
-        }
+fn remember_device(devices: &mut Vec<String>, device: &str) {
+    if !devices.iter().any(|d| d == device) {
+        devices.push(device.to_string());
     }
 }
-async fn set_door_lamps_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    lamps: [u8; 5],
-    client: &mut StreamDeckClient,
-) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            // first we have to determine which index is our state
-            let mut doorselector = get_value_or_empty(&btn.settings, "DoorSelector");
-            if doorselector.is_empty() {
-                doorselector = "Door 1".to_string();
-            }
 
-            let doorindex = match doorselector.as_str() {
-                "Door 1" => 1,
-                "Door 2" => 2,
-                "Door 3" => 3,
-                "Door 4" => 4,
-                _ => 0,
-            };
-
-            let state = lamps[doorindex];
-
-            if btn.state != state {
-                btn.state = state;
-                let mut gear = get_value_or_empty(&btn.settings, "GearSelection");
-                if gear.is_empty() {
-                    gear = "2".to_string();
-                }
-
-                let mut active = "off";
-                if btn.state == 1 {
-                    active = "on";
-                } else {
-                    active = "off";
-                }
-
-                let mut image = format!("actions/assets/doorbutton_{}.png", active);
-
-                if doorselector == "Clearance" {
-                    image = format!("actions/assets/doorclearance_{}.png", active);
-                }
-
-                let _ = client
-                    .transmitter
-                    .set_image(
-                        context.clone(),
-                        image,
-                        StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                        None,
-                    )
-                    .await;
-            }
-        }
+/// The extra telemetry requests the visible keys need.
+fn get_info_requests(buttons: &HashMap<String, ActionInstance>) -> InfoRequests {
+    InfoRequests {
+        payment: buttons.values().any(|b| b.uuid == action_payment_status::UUID),
     }
 }
 
-async fn set_indicator_lamps_for_uuid(
-    buttons: &mut HashMap<String, ActionInstance>,
-    uuid: &str,
-    lamps: [u8; 3],
-    client: &mut StreamDeckClient,
-) {
-    for (context, btn) in buttons.iter_mut() {
-        if btn.uuid == uuid {
-            // first we have to determine which index is our state
-            let mut indicatorselector = get_value_or_empty(&btn.settings, "IndicatorSelector");
-            if indicatorselector.is_empty() {
-                indicatorselector = "IndicatorLeft".to_string();
-            }
-
-            let indicatorindex = match indicatorselector.as_str() {
-                "WarningLights" => 0,
-                "IndicatorLeft" => 1,
-                "IndicatorRight" => 2,
-                _ => 0,
-            };
-
-            let state = lamps[indicatorindex];
-
-            if btn.state != state {
-                btn.state = state;
-                let mut image = "".to_string();
-                if state == 0 {
-                    image = get_indicator_image_off(indicatorselector.as_str());
-                } else {
-                    image = get_indicator_image_on(indicatorselector.as_str());
-                }
-
-                let _ = client
-                    .transmitter
-                    .set_image(
-                        context.clone(),
-                        image,
-                        StreamDeckTarget::HARDWARE_AND_SOFTWARE,
-                        None,
-                    )
-                    .await;
-            }
-        }
-    }
+fn build_registry(
+    rediscover: Arc<Notify>,
+    interlock: Interlock,
+    telemetry_rx: watch::Receiver<TelemetrySnapshot>,
+    target_rx: watch::Receiver<TargetSettings>,
+    failed_tx: mpsc::UnboundedSender<(String, String)>,
+) -> ActionRegistry {
+    let mut registry = ActionRegistry::new(interlock.clone());
+    registry.register(Box::new(action_inbus::InBusAction));
+    registry.register(Box::new(action_fixing_brake::FixingBrakeAction));
+    registry.register(Box::new(action_fixing_gearselect::GearSelectAction::new(
+        interlock.clone(),
+        telemetry_rx.clone(),
+    )));
+    registry.register(Box::new(action_ignition::IgnitionAction));
+    registry.register(Box::new(action_passenger_doors::PassengerDoorsAction::new(
+        interlock.clone(),
+        telemetry_rx.clone(),
+    )));
+    registry.register(Box::new(action_indicators::IndicatorsAction::new(
+        telemetry_rx.clone(),
+    )));
+    registry.register(Box::new(action_stop_brake::StopBrakeAction));
+    registry.register(Box::new(action_change::ChangeAction));
+    registry.register(Box::new(action_connection_status::ConnectionStatusAction::new(
+        rediscover,
+    )));
+    registry.register(Box::new(action_payment_status::PaymentStatusAction));
+    registry.register(Box::new(action_custom::CustomAction));
+    registry.register(Box::new(action_custom_button::CustomButtonAction));
+    registry.register(Box::new(action_start::StartAction));
+    registry.register(Box::new(action_speedometer::SpeedometerAction::new(
+        telemetry_rx.clone(),
+    )));
+    registry.register(Box::new(action_fuel::FuelAction::new(telemetry_rx.clone())));
+    registry.register(Box::new(action_dial::DialAction::new(failed_tx.clone())));
+    registry.register(Box::new(action_lights::LightsAction));
+    registry.register(Box::new(action_stop_request::StopRequestAction));
+    registry.register(Box::new(action_sequence::SequenceAction::new(
+        telemetry_rx,
+        target_rx,
+        interlock,
+        failed_tx,
+    )));
+    registry
 }
 
 #[tokio::main(worker_threads = 1)]
 async fn main() {
     let args = StreamDeckArgs::new();
     let client = connect_streamdeck(&args).await;
+    // lets the connection status key restart the search for the current vehicle
+    let rediscover = Arc::new(Notify::new());
     let mut buttons: HashMap<String, ActionInstance> = HashMap::new();
 
     let mut log_file = OpenOptions::new()
@@ -382,153 +317,203 @@ async fn main() {
         .open("streamdeck.log")
         .expect("Failed to open or create streamdeck.log");
 
-    let mut vehicle_name = "".to_string();
-
     let mut config = RequestConfig::new();
 //    config.debugging = true;
-
-    let mut vehicle_state = VehicleState::default();
-
-    let mut zaehler = 0;
-
-    let mut door_lamps = [0; 5];
-
-    let mut indicator_lamps = [0; 3];
-    let mut blinken_lights = 0;
+    apply_target(&mut config, &TargetSettings::default());
+
+    let mut blinken_lights = false;
+    let mut theme = Theme::default();
+    let profiles = ProfileDatabase::load();
+    let mut profile = VehicleProfile::default();
+    // Stream Deck devices and the profile last switched to on them
+    let mut devices: Vec<String> = Vec::new();
+    // only these have the type the deck profiles are made for
+    let profile_devices = get_profile_devices(&get_info_argument());
+    let mut switch_settings = ProfileSwitchSettings::default();
+    let mut deck_profile: Option<String> = None;
+
+    // global settings win over targets found in the settings of single buttons
+    let mut has_global_target = false;
+    let (target_tx, target_rx) = watch::channel(TargetSettings::default());
+    let (interlock_tx, interlock_rx) = watch::channel(InterlockSettings::default());
+    let (info_tx, info_rx) = watch::channel(InfoRequests::default());
 
     if let Ok(mut client) = client {
-        let mut ticker = tokio::time::interval(Duration::from_millis(300));
+        let (changes_tx, mut changes_rx) = broadcast::channel(STATE_CHANGE_CAPACITY);
+        let mut telemetry_rx = spawn_telemetry_task(target_rx.clone(), rediscover.clone(), changes_tx, info_rx);
+        // sequences and dial steps that failed in their task, as (context, reason)
+        let (failed_tx, mut failed_rx) = mpsc::unbounded_channel();
+        let interlock = Interlock::new(interlock_rx, telemetry_rx.clone());
+        let registry = build_registry(
+            rediscover,
+            interlock,
+            telemetry_rx.clone(),
+            target_rx,
+            failed_tx,
+        );
+
+        client
+            .transmitter
+            .get_global_settings(args.plugin_uuid.clone())
+            .await;
 
         loop {
+            let gesture_deadline = registry.next_gesture_deadline();
+
             tokio::select! {
-                                maybe_event = client.received_events.recv() => {
-                                    match maybe_event {
-                                                        Some(event) => {
-
-                                                                if config.debugging {
-                                                                    let line = describe_event(&event);
-                                                                    if let Err(e) = writeln!(log_file, "{}", line) {
-                                                                        eprintln!("Failed to write to log file: {}", e);
-                                                                    }
-                                                                    write_all_buttons_to_log(&mut log_file, &buttons);
-                                                                }
-
-                                                            let action = match &event {
-                                                                EventReceived::WillAppear(e) => e.action.clone(),
-                                                                EventReceived::WillDisappear(e) => e.action.clone(),
-                                                                EventReceived::TitleParametersDidChange(e) => e.action.clone(),
-                                                                EventReceived::KeyDown(e) => e.action.clone() ,
-                                                                EventReceived::KeyUp(e) => e.action.clone(),
-                                                                EventReceived::PropertyInspectorDidAppear(e) => e.action.clone() ,
-                                                                EventReceived::SendToPlugin(e) => e.action.clone() ,
-                                                                EventReceived::TouchTap(e) => e.action.clone(),
-                                                                EventReceived::DidReceiveSettings(e) => e.action.clone(),
-                                                                EventReceived::DialPress(e) => e.action.clone() ,
-                                                                EventReceived::DialRotate(e) => e.action.clone(),
-                                                                _ => "".to_string(),
-                                                            };
-
-                                                            // we only care about events that have an action entry (are about a button/instance )
-                                                            if action == UUID_INBUS { handle_event_inbus(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_FIXING_BRAKE { handle_event_fixing_brake(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_GEARSELECT { handle_event_fixing_gearselect(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_IGNITION { handle_event_ignition(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_PASSENGER_DOORS { handle_event_passenger_doors(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_INDICATORS { handle_event_indicators(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_STOP_BRAKE { handle_event_stop_brake(event,&config, &mut buttons, &mut client).await; }
-                                                            else if action == UUID_CHANGE { handle_event_change(event,&config, &mut buttons, &mut client).await; }
-
-                                                        }
-                                                        None => break,
+                maybe_event = client.received_events.recv() => {
+                    match maybe_event {
+                        Some(event) => {
+                            if config.debugging {
+                                let line = describe_event(&event);
+                                if let Err(e) = writeln!(log_file, "{}", line) {
+                                    eprintln!("Failed to write to log file: {}", e);
+                                }
+                                write_all_buttons_to_log(&mut log_file, &buttons);
+                            }
+
+                            match &event {
+                                EventReceived::DeviceDidConnect(e) => remember_device(&mut devices, &e.device),
+                                // devices connected before the plugin started are only known from their keys
+                                EventReceived::WillAppear(e) => remember_device(&mut devices, &e.device),
+                                EventReceived::DeviceDidDisconnect(e) => devices.retain(|d| *d != e.device),
+                                _ => {}
+                            }
+
+                            match &event {
+                                EventReceived::DidReceiveGlobalSettings(e) => {
+                                    match parse_settings::<GlobalSettings>(&e.payload.settings) {
+                                        Ok(settings) => {
+                                            has_global_target = true;
+                                            update_target(&mut config, &target_tx, settings.target);
+                                            interlock_tx.send_replace(settings.interlock);
+                                            switch_settings = settings.profiles;
+
+                                            let new_theme = Theme::from_settings(&settings.theme);
+                                            if new_theme != theme {
+                                                theme = new_theme;
+                                                registry.render_all(&config, &theme, &profile, &mut buttons, &mut client).await;
+                                            }
+                                        }
+                                        Err(err) => logger(&format!("Invalid global settings: {}", err)),
                                     }
-                                },
-
-                                _ = ticker.tick() => {
-
-                                                    if (vehicle_name.is_empty()) || (zaehler>10){
-                                                        config.vehicle_name = "Current".to_string();
-                                                        vehicle_name = get_current_vehicle_name(&config).await;
-                                                        zaehler = 0;
-                                                    }
-
-                                                    if vehicle_name.is_empty() {
-                                                        vehicle_state = VehicleState::default();
-                                                        set_state_for_uuid(&mut buttons, UUID_INBUS, 0, &mut client).await;
-
-                                                    } else {
-
-                                                    config.vehicle_name = vehicle_name.clone();
-
-                                                    let vehicle_response = get_vehicle(&config).await;
-                                                    if vehicle_response.is_err() {
-                                                        // println!("Error getting vehicle data in JSON.");
-                                                        vehicle_name = "".to_string();
-                                                        zaehler = 12;
-
-                                                    } else {
-
-                                                    zaehler = zaehler + 1;
-
-                                                    let vehicle = vehicle_response.unwrap();
-                                                    // println!("{:?}", vehicle);
-                                                    if config.vehicle_model != vehicle.vehicle_model {
-                                                        config.vehicle_model = vehicle.vehicle_model.clone();
-                                                    }
-
-                                                    let new_vehicle_state = get_vehicle_state_from_api(vehicle);
-
-                                                    if config.debugging {
-                                                        new_vehicle_state.print();
-                                                    }
-
-                                                    if config.debugging {
-                                                        vehicle_state.compare(&new_vehicle_state, false, None);
-                                                    }
-
-                                                    vehicle_state = new_vehicle_state;
-
-                                                    if blinken_lights == 0 {
-                                                        blinken_lights = 1;
-                                                    } else {
-                                                        blinken_lights = 0;
-                                                    }
-                                                    // indicator: left:1, right: 2, none/off: 0
-                                                    // lights_warning: 1:on 0:off
-                                                    // indicator_lamps[0]=blinken_lights;
-
-                                                    indicator_lamps[0]=if vehicle_state.lights_warning { 1 } else { 0 };
-                                                    indicator_lamps[1]=0;
-                                                    indicator_lamps[2]=0;
-
-                                                    if (vehicle_state.indicator == 1) || (vehicle_state.lights_warning) {
-                                                        indicator_lamps[1]=blinken_lights;
-                                                    }
-                                                    if (vehicle_state.indicator == 2) || (vehicle_state.lights_warning) {
-                                                        indicator_lamps[2]=blinken_lights;
-                                                    }
-
-                                                    set_indicator_lamps_for_uuid(&mut buttons, UUID_INDICATORS, indicator_lamps, &mut client).await;
-
-
-                                                    set_state_for_uuid(&mut buttons, UUID_INBUS, 1, &mut client).await;
-                                                    set_state_for_uuid(&mut buttons, UUID_FIXING_BRAKE, if vehicle_state.fixing_brake { 1 } else { 0 }, &mut client).await;
-
-                                                    set_gearselect_for_uuid(&mut buttons, UUID_GEARSELECT, vehicle_state.gear_selector, &mut client).await;
-
-                                                    // lamps
-                                                    door_lamps[0]=if vehicle_state.doors { 1 } else { 0 };
-                                                    door_lamps[1]=if vehicle_state.lights_front_door { 1 } else { 0 };
-                                                    door_lamps[2]=if vehicle_state.lights_second_door { 1 } else { 0 };
-                                                    door_lamps[3]=if vehicle_state.lights_third_door { 1 } else { 0 };
-                                                    door_lamps[4]=if vehicle_state.lights_fourth_door { 1 } else { 0 };
-                                                    set_door_lamps_for_uuid(&mut buttons, UUID_PASSENGER_DOORS, door_lamps, &mut client).await;
-
-                                                    set_state_for_uuid(&mut buttons, UUID_STOP_BRAKE, if vehicle_state.lights_stop_brake { 1 } else { 0 }, &mut client).await;
+                                }
+                                EventReceived::WillAppear(e) if !has_global_target => {
+                                    if let Some(Ok(target)) = target_from_settings(&e.payload.settings) {
+                                        update_target(&mut config, &target_tx, target);
+                                    }
+                                }
+                                EventReceived::DidReceiveSettings(e) if !has_global_target => {
+                                    if let Some(Ok(target)) = target_from_settings(&e.payload.settings) {
+                                        update_target(&mut config, &target_tx, target);
+                                    }
+                                }
+                                _ => {}
+                            }
+
+                            registry.handle_event(event, &config, &theme, &profile, &mut buttons, &mut client).await;
+                            // keys may have appeared or gone
+                            let info = get_info_requests(&buttons);
+                            if *info_tx.borrow() != info {
+                                info_tx.send_replace(info);
+                            }
+                        }
+                        None => break,
+                    }
+                },
+
+                changed = telemetry_rx.changed() => {
+                    if changed.is_err() {
+                        // telemetry task is gone
+                        break;
+                    }
+
+                    let snapshot = telemetry_rx.borrow_and_update().clone();
+
+                    config.vehicle_name = if snapshot.vehicle_name.is_empty() {
+                        "Current".to_string()
+                    } else {
+                        snapshot.vehicle_name.clone()
+                    };
+                    if config.vehicle_model != snapshot.vehicle_model {
+                        profile = match profiles.find(&snapshot.vehicle_model) {
+                            Some(known) => known.clone(),
+                            None => {
+                                if !snapshot.vehicle_model.is_empty() {
+                                    logger(&format!(
+                                        "No vehicle profile for {}, using the default one",
+                                        snapshot.vehicle_model
+                                    ));
+                                }
+                                VehicleProfile::default()
+                            }
+                        };
+                        profile.detect(&snapshot.buttons, &snapshot.lights);
+                        // keys for doors, gears or lights of the previous bus may be (un)available now
+                        registry.render_all(&config, &theme, &profile, &mut buttons, &mut client).await;
+                    }
+                    config.vehicle_model = snapshot.vehicle_model.clone();
+
+                    let in_bus = !snapshot.vehicle_name.is_empty();
+                    let wanted = get_deck_profile(&switch_settings, in_bus.then_some(&profile));
+                    if let Some(name) = wanted {
+                        let switched: Vec<&String> =
+                            devices.iter().filter(|d| profile_devices.contains(d)).collect();
+                        if deck_profile.as_deref() != Some(name) && !switched.is_empty() {
+                            logger(&format!("Switching to Stream Deck profile {}", name));
+                            for device in switched {
+                                client
+                                    .transmitter
+                                    .switch_to_profile(args.plugin_uuid.clone(), device.clone(), name.to_string())
+                                    .await;
+                            }
+                            deck_profile = Some(name.to_string());
+                        }
+                    }
+
+                    if snapshot.in_bus {
+                        blinken_lights = !blinken_lights;
+                    }
+
+                    let telemetry = Telemetry {
+                        in_bus: snapshot.in_bus,
+                        status: snapshot.status,
+                        vehicle_model: &snapshot.vehicle_model,
+                        latency: snapshot.latency,
+                        vehicle_state: &snapshot.vehicle_state,
+                        buttons: &snapshot.buttons,
+                        lights: &snapshot.lights,
+                        payment: snapshot.payment.as_ref(),
+                        blink: blinken_lights,
+                    };
+                    registry.update_telemetry(&telemetry, &config, &theme, &profile, &mut buttons, &mut client).await;
+                }
 
-                                                    }
-                                        }
+                change = changes_rx.recv() => {
+                    match change {
+                        Ok(change) => {
+                            registry.handle_state_change(&change, &config, &theme, &profile, &mut buttons, &mut client).await;
+                        }
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            logger(&format!("Missed {} vehicle state changes", missed));
+                            registry.handle_changes_missed(&config, &theme, &profile, &mut buttons, &mut client).await;
+                        }
+                        // telemetry task is gone
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                },
+
+                _ = tokio::time::sleep_until(gesture_deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if gesture_deadline.is_some() => {
+                    registry.expire_gestures(&config, &theme, &profile, &mut buttons, &mut client).await;
+                }
 
-                                }
+                Some((context, reason)) = failed_rx.recv() => {
+                    logger(&format!("Action {} aborted: {}", context, reason));
+                    client.transmitter.show_alert(context.clone()).await;
+                    // a dial shows where its steps got the lever
+                    registry.render(&context, &config, &theme, &profile, &mut buttons, &mut client).await;
+                }
             }
         }
     }
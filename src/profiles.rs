@@ -0,0 +1,305 @@
+//! What differs between the buses in the game: event names, doors, gears, lights.
+//!
+//! The profiles are embedded from `vehicle_profiles.json`. A file with the same name
+//! in the plugin folder is read first, so its profiles win over the embedded ones.
+
+use std::collections::HashMap;
+use std::fs;
+
+use serde::Deserialize;
+
+// Access items from the parent module (main.rs)
+use super::logger;
+use super::settings::{DoorSelector, Gear, LightSelector, ProfileSwitchSettings};
+
+/// komsi does not know the fog and interior lights, they are read from the lights of the vehicle
+pub const LIGHT_FOG: &str = "Fog Light";
+pub const LIGHT_INTERIOR: &str = "Interior Light";
+
+/// The device type the deck profiles are made for (the Stream Deck with 15 keys), see
+/// `Profiles` in manifest.json. Other devices (Stream Deck+, Mini, XL) are never switched.
+pub const DECK_PROFILE_DEVICE_TYPE: u64 = 0;
+
+const EMBEDDED_PROFILES: &str = include_str!("vehicle_profiles.json");
+/// Profiles added or changed by the user, next to the plugin binary.
+const PROFILES_FILE: &str = "vehicle_profiles.json";
+
+/// One kind of bus. Everything not given in the JSON is like the MAN Lion's City.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VehicleProfile {
+    /// parts of the model names (as reported by the game) this profile is for, ignoring case
+    pub models: Vec<String>,
+    pub doors: u8,
+    /// letters of the gears on the gear selector
+    pub gears: Vec<String>,
+    pub lights: Vec<LightSelector>,
+    /// reports the battery charge as fuel level
+    pub electric: bool,
+    /// Stream Deck profile shown while driving this bus, empty for the one from the global settings
+    pub deck_profile: String,
+    /// event names of this bus, keyed by the event name of the Lion's City
+    events: HashMap<String, String>,
+}
+
+impl Default for VehicleProfile {
+    fn default() -> Self {
+        VehicleProfile {
+            models: Vec::new(),
+            doors: 3,
+            gears: vec!["D".to_string(), "N".to_string(), "R".to_string()],
+            lights: vec![
+                LightSelector::MainLight,
+                LightSelector::HighBeam,
+                LightSelector::FogLight,
+                LightSelector::InteriorLight,
+            ],
+            electric: false,
+            deck_profile: String::new(),
+            events: HashMap::new(),
+        }
+    }
+}
+
+impl VehicleProfile {
+    /// The name of `event` (as the Lion's City calls it) in this bus.
+    pub fn event<'a>(&'a self, event: &'a str) -> &'a str {
+        self.events.get(event).map(String::as_str).unwrap_or(event)
+    }
+
+    /// The Lion's City name of an event of this bus, the other way round than `event`.
+    pub fn lions_city_event<'a>(&'a self, event: &'a str) -> &'a str {
+        self.events
+            .iter()
+            .find(|(_, name)| *name == event)
+            .map(|(lions_city, _)| lions_city.as_str())
+            .unwrap_or(event)
+    }
+
+    pub fn has_door(&self, door: DoorSelector) -> bool {
+        let number = match door {
+            DoorSelector::Door1 => 1,
+            DoorSelector::Door2 => 2,
+            DoorSelector::Door3 => 3,
+            DoorSelector::Door4 => 4,
+            DoorSelector::Clearance => return true,
+        };
+        number <= self.doors
+    }
+
+    pub fn has_gear(&self, gear: Gear) -> bool {
+        self.gears.iter().any(|g| g == gear.letter())
+    }
+
+    pub fn has_light(&self, light: LightSelector) -> bool {
+        self.lights.contains(&light)
+    }
+
+    /// Adjusts the profile to the door buttons and lights the bus reports,
+    /// so buses without a profile (or with a wrong one) get the right keys.
+    pub fn detect(&mut self, buttons: &HashMap<String, String>, lights: &HashMap<String, String>) {
+        let doors = buttons
+            .keys()
+            .filter_map(|name| name.strip_prefix("Door ")?.parse::<u8>().ok())
+            .max();
+        if let Some(doors) = doors {
+            self.doors = doors;
+        }
+
+        // without any lights the game did not tell us anything
+        if !lights.is_empty() {
+            self.lights.retain(|light| match light {
+                LightSelector::FogLight => lights.contains_key(LIGHT_FOG),
+                LightSelector::InteriorLight => lights.contains_key(LIGHT_INTERIOR),
+                LightSelector::MainLight | LightSelector::HighBeam => true,
+            });
+        }
+    }
+
+    fn matches(&self, model: &str) -> bool {
+        let model = model.to_lowercase();
+        self.models.iter().any(|m| model.contains(&m.to_lowercase()))
+    }
+}
+
+/// The Stream Deck profile to switch to, for the bus the player sits in (None if not in a bus).
+/// None if switching is off or no profile is set for the case.
+pub fn get_deck_profile<'a>(
+    settings: &'a ProfileSwitchSettings,
+    profile: Option<&'a VehicleProfile>,
+) -> Option<&'a str> {
+    if !settings.enabled {
+        return None;
+    }
+    let name = match profile {
+        Some(profile) if !profile.deck_profile.is_empty() => &profile.deck_profile,
+        Some(_) => &settings.bus,
+        None => &settings.depot,
+    };
+    (!name.is_empty()).then_some(name.as_str())
+}
+
+/// The devices a deck profile can be shown on, from the `-info` argument the Stream Deck
+/// software starts the plugin with. A device missing there (or an unreadable `info`) is not
+/// switched, its type is unknown.
+pub fn get_profile_devices(info: &str) -> Vec<String> {
+    let info: serde_json::Value = serde_json::from_str(info).unwrap_or_default();
+    info["devices"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|device| device["type"].as_u64() == Some(DECK_PROFILE_DEVICE_TYPE))
+        .filter_map(|device| device["id"].as_str().map(str::to_string))
+        .collect()
+}
+
+/// All known profiles, the first one matching a model is used.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileDatabase {
+    profiles: Vec<VehicleProfile>,
+}
+
+impl ProfileDatabase {
+    /// The embedded profiles, after the ones from the plugin folder (if there are any).
+    pub fn load() -> Self {
+        let mut profiles = match fs::read_to_string(PROFILES_FILE) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                logger(&format!("Invalid {}: {}", PROFILES_FILE, e));
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        profiles.extend(Self::embedded().profiles);
+        ProfileDatabase { profiles }
+    }
+
+    pub fn embedded() -> Self {
+        ProfileDatabase {
+            profiles: serde_json::from_str(EMBEDDED_PROFILES).expect("embedded vehicle profiles are valid"),
+        }
+    }
+
+    /// None for models without a profile, they should be added to `vehicle_profiles.json`.
+    pub fn find(&self, model: &str) -> Option<&VehicleProfile> {
+        if model.is_empty() {
+            return None;
+        }
+        self.profiles.iter().find(|p| p.matches(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_profile() {
+        let profiles = ProfileDatabase::embedded();
+
+        let citea = profiles.find("VDL Citea LLE-120").unwrap();
+        assert_eq!(citea.event("DoorMiddleOpenClose"), "MiddleDoorOpenClose");
+        assert_eq!(citea.event("DoorFrontOpenClose"), "DoorFrontOpenClose");
+        assert_eq!(citea.lions_city_event("MiddleDoorOpenClose"), "DoorMiddleOpenClose");
+        assert_eq!(citea.lions_city_event("Kneeling"), "Kneeling");
+        assert!(!citea.has_door(DoorSelector::Door3));
+
+        assert!(profiles.find("MAN Lion's City 18E").unwrap().electric);
+        assert!(profiles.find("MAN Lion's City 18").unwrap().has_door(DoorSelector::Door4));
+        assert!(profiles.find("Mercedes-Benz eCitaro").unwrap().electric);
+        assert!(!profiles.find("MAN Lion's City 12m").unwrap().electric);
+
+        assert!(profiles.find("Some Bus").is_none());
+        assert!(profiles.find("").is_none());
+    }
+
+    #[test]
+    fn test_bundled_deck_profiles() {
+        // the Stream Deck software only switches to profiles listed in the manifest
+        let manifest: serde_json::Value =
+            serde_json::from_str(include_str!("../assets/manifest.json")).unwrap();
+        let bundled: Vec<&str> = manifest["Profiles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|profile| profile["Name"].as_str().unwrap())
+            .collect();
+        assert!(bundled.contains(&"profiles/Depot"));
+        // only devices of this type are switched
+        for profile in manifest["Profiles"].as_array().unwrap() {
+            assert_eq!(profile["DeviceType"].as_u64(), Some(DECK_PROFILE_DEVICE_TYPE));
+        }
+        for profile in ProfileDatabase::embedded().profiles {
+            let name = profile.deck_profile.as_str();
+            assert!(bundled.contains(&name), "{} is not in the manifest", name);
+        }
+    }
+
+    #[test]
+    fn test_default_profile() {
+        let profile = VehicleProfile::default();
+        assert_eq!(profile.event("SetGearD"), "SetGearD");
+        assert!(profile.has_door(DoorSelector::Door3));
+        assert!(!profile.has_door(DoorSelector::Door4));
+        assert!(profile.has_door(DoorSelector::Clearance));
+        assert!(profile.has_gear(Gear::R));
+        assert!(profile.has_light(LightSelector::FogLight));
+    }
+
+    #[test]
+    fn test_detect() {
+        let names = |names: &[&str]| -> HashMap<String, String> {
+            names.iter().map(|n| (n.to_string(), "Off".to_string())).collect()
+        };
+
+        let mut profile = VehicleProfile::default();
+        profile.detect(&names(&["Door 1", "Door 2", "Door Clearance"]), &names(&["Main Light", LIGHT_FOG]));
+        assert_eq!(profile.doors, 2);
+        assert!(profile.has_light(LightSelector::FogLight));
+        assert!(!profile.has_light(LightSelector::InteriorLight));
+        assert!(profile.has_light(LightSelector::HighBeam));
+
+        // nothing reported, nothing changed
+        let mut profile = VehicleProfile::default();
+        profile.detect(&HashMap::new(), &HashMap::new());
+        assert_eq!(profile, VehicleProfile::default());
+    }
+
+    #[test]
+    fn test_get_deck_profile() {
+        let mut settings = ProfileSwitchSettings {
+            enabled: true,
+            bus: "The Bus".to_string(),
+            depot: "Depot".to_string(),
+        };
+        let citea: VehicleProfile = serde_json::from_str(r#"{ "deck_profile": "Citea" }"#).unwrap();
+        assert_eq!(get_deck_profile(&settings, Some(&citea)), Some("Citea"));
+        assert_eq!(get_deck_profile(&settings, Some(&VehicleProfile::default())), Some("The Bus"));
+        assert_eq!(get_deck_profile(&settings, None), Some("Depot"));
+
+        settings.depot = "".to_string();
+        assert_eq!(get_deck_profile(&settings, None), None);
+        settings.enabled = false;
+        assert_eq!(get_deck_profile(&settings, Some(&citea)), None);
+    }
+
+    #[test]
+    fn test_get_profile_devices() {
+        let info = r#"{ "devices": [
+            { "id": "DECK", "type": 0 },
+            { "id": "PLUS", "type": 7 },
+            { "id": "MINI", "type": 1 }
+        ] }"#;
+        assert_eq!(get_profile_devices(info), vec!["DECK"]);
+        assert!(get_profile_devices("").is_empty());
+    }
+
+    #[test]
+    fn test_partial_profile() {
+        let profile: VehicleProfile =
+            serde_json::from_str(r#"{ "models": ["Minibus"], "gears": ["D", "R"], "lights": ["MainLight"] }"#)
+                .unwrap();
+        assert!(!profile.has_gear(Gear::N));
+        assert!(!profile.has_light(LightSelector::HighBeam));
+        assert_eq!(profile.doors, 3);
+    }
+}
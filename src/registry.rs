@@ -0,0 +1,567 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use komsi::vehicle::VehicleState;
+use rusty_patio::streamdeck::{client::StreamDeckClient, events::event_received::EventReceived};
+use the_bus_telemetry::api::{send_telemetry_bus_cmd, RequestConfig};
+use tokio::time::Instant;
+
+// Access items from the parent module (main.rs)
+use super::gesture::{Gesture, GestureDetector};
+use super::interlock::Interlock;
+use super::profiles::VehicleProfile;
+use super::settings::{parse_settings, GestureSettings};
+use super::state_diff::StateChange;
+use super::telemetry::{ConnectionStatus, PaymentState};
+use super::theme::Theme;
+use super::{logger, ActionInstance};
+
+/// Everything an action may need while handling one of its buttons.
+pub struct ActionContext<'a> {
+    /// Stream Deck context of the button that is being handled
+    pub context: &'a str,
+    pub config: &'a RequestConfig,
+    /// icon pack chosen in the global settings
+    pub theme: &'a Theme,
+    /// profile of the current bus (or the default one)
+    pub profile: &'a VehicleProfile,
+    pub client: &'a mut StreamDeckClient,
+}
+
+/// What every button gets to see on each telemetry tick.
+pub struct Telemetry<'a> {
+    /// false if the player is not sitting in a bus (or the game is not running)
+    pub in_bus: bool,
+    pub status: ConnectionStatus,
+    pub vehicle_model: &'a str,
+    /// how long the last vehicle request took, None if there was none
+    pub latency: Option<Duration>,
+    pub vehicle_state: &'a VehicleState,
+    pub buttons: &'a HashMap<String, String>,
+    pub lights: &'a HashMap<String, String>,
+    pub payment: Option<&'a PaymentState>,
+    /// toggles on every tick, used for blinking lamps
+    pub blink: bool,
+}
+
+/// One kind of Stream Deck action (one UUID from the manifest).
+///
+/// All methods have empty defaults, so an action only implements what it needs.
+/// `render` is expected to draw the button from `button.state` and its settings.
+#[async_trait(?Send)]
+pub trait Action {
+    fn uuid(&self) -> &'static str;
+
+    /// Turns the raw settings into the action's own settings type,
+    /// see `settings::parse_boxed_settings`.
+    fn parse_settings(
+        &self,
+        _settings: &HashMap<String, serde_json::Value>,
+    ) -> Result<Box<dyn Any>, serde_json::Error> {
+        Ok(Box::new(()))
+    }
+
+    async fn on_will_appear(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        ctx.client
+            .transmitter
+            .set_state(ctx.context.to_string(), 0)
+            .await;
+        self.render(ctx, button).await;
+    }
+
+    /// Called after new settings of the button have been parsed.
+    async fn on_did_receive_settings(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.render(ctx, button).await;
+    }
+
+    /// Called before the button is forgotten, e.g. on a page switch.
+    async fn on_will_disappear(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {}
+
+    async fn on_key_down(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {}
+
+    async fn on_key_up(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {}
+
+    async fn on_dial_rotate(
+        &self,
+        _ctx: &mut ActionContext<'_>,
+        _button: &mut ActionInstance,
+        _ticks: i64,
+    ) {
+    }
+
+    async fn on_dial_press(
+        &self,
+        _ctx: &mut ActionContext<'_>,
+        _button: &mut ActionInstance,
+        _pressed: bool,
+    ) {
+    }
+
+    async fn on_touch_tap(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {}
+
+    async fn on_telemetry_update(
+        &self,
+        _ctx: &mut ActionContext<'_>,
+        _button: &mut ActionInstance,
+        _telemetry: &Telemetry<'_>,
+    ) {
+    }
+
+    /// Called once for every change of the vehicle state, see `state_diff::diff_states`.
+    /// Unlike `on_telemetry_update` it is not called for ticks where nothing changed.
+    async fn on_state_change(
+        &self,
+        _ctx: &mut ActionContext<'_>,
+        _button: &mut ActionInstance,
+        _change: &StateChange,
+    ) {
+    }
+
+    /// Called when changes of the vehicle state were missed, a button that follows them has
+    /// to read the current state from the telemetry again.
+    async fn on_changes_missed(&self, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+        self.render(ctx, button).await;
+    }
+
+    async fn render(&self, _ctx: &mut ActionContext<'_>, _button: &mut ActionInstance) {}
+}
+
+/// All known actions, keyed by their UUID.
+pub struct ActionRegistry {
+    actions: HashMap<&'static str, Box<dyn Action>>,
+    /// keys with a hold or double tap event that are in the middle of a gesture
+    gestures: RefCell<GestureDetector>,
+    /// asked before a hold or double tap event is sent
+    interlock: Interlock,
+}
+
+impl ActionRegistry {
+    pub fn new(interlock: Interlock) -> Self {
+        ActionRegistry {
+            actions: HashMap::new(),
+            gestures: RefCell::new(GestureDetector::default()),
+            interlock,
+        }
+    }
+
+    pub fn register(&mut self, action: Box<dyn Action>) {
+        self.actions.insert(action.uuid(), action);
+    }
+
+    pub fn get(&self, uuid: &str) -> Option<&dyn Action> {
+        self.actions.get(uuid).map(|a| a.as_ref())
+    }
+
+    /// Routes a Stream Deck event to the action it belongs to.
+    /// Events for unknown UUIDs (or without an action at all) are ignored.
+    pub async fn handle_event(
+        &self,
+        event: EventReceived,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        match event {
+            EventReceived::WillAppear(event) => {
+                let Some(action) = self.get(&event.action) else {
+                    return;
+                };
+                if !buttons.contains_key(&event.context) {
+                    let btn = ActionInstance::new(&event.action, event.payload.settings);
+                    buttons.insert(event.context.clone(), btn);
+
+                    if let Some(button) = buttons.get_mut(&event.context) {
+                        let mut ctx = ActionContext {
+                            context: &event.context,
+                            config,
+                            theme,
+                            profile,
+                            client,
+                        };
+                        apply_settings(action, &mut ctx, button).await;
+                        action.on_will_appear(&mut ctx, button).await;
+                    }
+                }
+            }
+
+            EventReceived::WillDisappear(event) => {
+                self.gestures.borrow_mut().remove(&event.context);
+                if let (Some(action), Some(mut button)) =
+                    (self.get(&event.action), buttons.remove(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    action.on_will_disappear(&mut ctx, &mut button).await;
+                }
+            }
+
+            EventReceived::TitleParametersDidChange(event) => {
+                if let Some(button) = buttons.get_mut(&event.context) {
+                    if let Some(t) = event.payload.settings.get("title").and_then(|v| v.as_str()) {
+                        button.title = t.to_string();
+                    }
+                }
+            }
+
+            EventReceived::KeyDown(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    if button.gestures.is_empty() {
+                        action.on_key_down(&mut ctx, button).await;
+                    } else {
+                        let gesture = self.gestures.borrow_mut().key_down(
+                            &event.context,
+                            &button.gestures,
+                            Instant::now(),
+                        );
+                        if let Some(gesture) = gesture {
+                            run_gesture(action, &mut ctx, button, gesture, &self.interlock).await;
+                        }
+                    }
+                }
+            }
+
+            EventReceived::KeyUp(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    if button.gestures.is_empty() {
+                        action.on_key_up(&mut ctx, button).await;
+                    } else {
+                        let gesture = self.gestures.borrow_mut().key_up(
+                            &event.context,
+                            &button.gestures,
+                            Instant::now(),
+                        );
+                        if let Some(gesture) = gesture {
+                            run_gesture(action, &mut ctx, button, gesture, &self.interlock).await;
+                        }
+                    }
+                }
+            }
+
+            EventReceived::DialRotate(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    action
+                        .on_dial_rotate(&mut ctx, button, event.payload.ticks as i64)
+                        .await;
+                }
+            }
+
+            EventReceived::DialPress(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    action
+                        .on_dial_press(&mut ctx, button, event.payload.pressed)
+                        .await;
+                }
+            }
+
+            EventReceived::TouchTap(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    action.on_touch_tap(&mut ctx, button).await;
+                }
+            }
+
+            EventReceived::DidReceiveSettings(event) => {
+                if let (Some(action), Some(button)) =
+                    (self.get(&event.action), buttons.get_mut(&event.context))
+                {
+                    button.settings = event.payload.settings;
+                    let mut ctx = ActionContext {
+                        context: &event.context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    apply_settings(action, &mut ctx, button).await;
+                    action.on_did_receive_settings(&mut ctx, button).await;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Hands the latest telemetry to every visible button.
+    pub async fn update_telemetry(
+        &self,
+        telemetry: &Telemetry<'_>,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        for (context, button) in buttons.iter_mut() {
+            if let Some(action) = self.get(&button.uuid) {
+                let mut ctx = ActionContext {
+                    context,
+                    config,
+                    theme,
+                    profile,
+                    client,
+                };
+                action.on_telemetry_update(&mut ctx, button, telemetry).await;
+            }
+        }
+    }
+
+    /// Hands one change of the vehicle state to every visible button.
+    pub async fn handle_state_change(
+        &self,
+        change: &StateChange,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        for (context, button) in buttons.iter_mut() {
+            if let Some(action) = self.get(&button.uuid) {
+                let mut ctx = ActionContext {
+                    context,
+                    config,
+                    theme,
+                    profile,
+                    client,
+                };
+                action.on_state_change(&mut ctx, button, change).await;
+            }
+        }
+    }
+
+    /// Lets every visible button catch up on changes of the vehicle state it missed.
+    pub async fn handle_changes_missed(
+        &self,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        for (context, button) in buttons.iter_mut() {
+            if let Some(action) = self.get(&button.uuid) {
+                let mut ctx = ActionContext {
+                    context,
+                    config,
+                    theme,
+                    profile,
+                    client,
+                };
+                action.on_changes_missed(&mut ctx, button).await;
+            }
+        }
+    }
+
+    /// When `expire_gestures` has to be called next, None if no key is in the middle of a gesture.
+    pub fn next_gesture_deadline(&self) -> Option<Instant> {
+        self.gestures.borrow().next_deadline()
+    }
+
+    /// Runs the holds and the single taps that are complete by now.
+    pub async fn expire_gestures(
+        &self,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        let expired = self.gestures.borrow_mut().expire(Instant::now());
+        for (context, gesture) in expired {
+            if let Some(button) = buttons.get_mut(&context) {
+                if let Some(action) = self.get(&button.uuid) {
+                    let mut ctx = ActionContext {
+                        context: &context,
+                        config,
+                        theme,
+                        profile,
+                        client,
+                    };
+                    run_gesture(action, &mut ctx, button, gesture, &self.interlock).await;
+                }
+            }
+        }
+    }
+
+    /// Draws one button again, if it is still visible.
+    pub async fn render(
+        &self,
+        context: &str,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        let Some(button) = buttons.get_mut(context) else {
+            return;
+        };
+        if let Some(action) = self.get(&button.uuid) {
+            let mut ctx = ActionContext {
+                context,
+                config,
+                theme,
+                profile,
+                client,
+            };
+            action.render(&mut ctx, button).await;
+        }
+    }
+
+    /// Draws every visible button again, e.g. after the icon theme has changed.
+    pub async fn render_all(
+        &self,
+        config: &RequestConfig,
+        theme: &Theme,
+        profile: &VehicleProfile,
+        buttons: &mut HashMap<String, ActionInstance>,
+        client: &mut StreamDeckClient,
+    ) {
+        for (context, button) in buttons.iter_mut() {
+            if let Some(action) = self.get(&button.uuid) {
+                let mut ctx = ActionContext {
+                    context,
+                    config,
+                    theme,
+                    profile,
+                    client,
+                };
+                action.render(&mut ctx, button).await;
+            }
+        }
+    }
+}
+
+/// Parses the raw settings of a button. Invalid settings are logged and
+/// the key shows an alert, the button keeps its previous (or default) settings.
+async fn apply_settings(action: &dyn Action, ctx: &mut ActionContext<'_>, button: &mut ActionInstance) {
+    // both or neither, a button never mixes new gestures with old settings
+    let parsed = parse_settings::<GestureSettings>(&button.settings).and_then(|gestures| {
+        action.parse_settings(&button.settings).map(|parsed| (gestures, parsed))
+    });
+    match parsed {
+        Ok((gestures, parsed)) => {
+            button.gestures = gestures;
+            button.parsed_settings = parsed;
+        }
+        Err(e) => {
+            logger(&format!(
+                "Invalid settings for {} {}: {}",
+                button.uuid, ctx.context, e
+            ));
+            ctx.client
+                .transmitter
+                .show_alert(ctx.context.to_string())
+                .await;
+        }
+    }
+}
+
+/// A tap is a plain key press for the action, holds and double taps send their own event.
+/// That event goes through the interlocks like a sequence step, a refused or failed
+/// event is logged and the key shows an alert.
+async fn run_gesture(
+    action: &dyn Action,
+    ctx: &mut ActionContext<'_>,
+    button: &mut ActionInstance,
+    gesture: Gesture,
+    interlock: &Interlock,
+) {
+    let event = match gesture {
+        Gesture::Tap => {
+            action.on_key_down(ctx, button).await;
+            action.on_key_up(ctx, button).await;
+            return;
+        }
+        Gesture::Hold => &button.gestures.hold,
+        Gesture::DoubleTap => &button.gestures.double_tap,
+    };
+    if let Err(reason) = interlock.check_event(ctx.profile, event) {
+        logger(&format!("Interlock: {}", reason));
+        super::show_alert_for_button(ctx).await;
+        return;
+    }
+    let cmd = format!("sendevent?event={}", ctx.profile.event(event));
+    if send_telemetry_bus_cmd(ctx.config, &cmd).await.is_err() {
+        logger(&format!("Gesture command {} failed", cmd));
+        super::show_alert_for_button(ctx).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct DummyAction;
+
+    #[async_trait(?Send)]
+    impl Action for DummyAction {
+        fn uuid(&self) -> &'static str {
+            "de.thatzok.thebus.dummy"
+        }
+    }
+
+    #[test]
+    fn test_register_and_get() {
+        let mut registry = ActionRegistry::new(Interlock::disabled());
+        registry.register(Box::new(DummyAction));
+
+        assert!(registry.get("de.thatzok.thebus.dummy").is_some());
+        assert!(registry.get("de.thatzok.thebus.unknown").is_none());
+        assert!(registry.get("").is_none());
+    }
+}
@@ -0,0 +1,244 @@
+//! Composes key images from layers (background, icon, lamp, text) instead of
+//! shipping a PNG for every combination.
+//!
+//! Images are built as SVG and sent as base64 data URLs. The output only depends
+//! on the layers and the icon files, so it can be compared with snapshots in tests.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+/// Size of a key image in pixels (high DPI keys).
+pub const KEY_SIZE: u32 = 144;
+
+pub const COLOR_BACKGROUND: &str = "#000000";
+pub const COLOR_LAMP_ON: &str = "#34c759";
+pub const COLOR_LAMP_OFF: &str = "#3a3a3a";
+pub const COLOR_TEXT: &str = "#ffffff";
+
+/// Where a text layer sits on the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Layer {
+    Background(String),
+    /// path of a PNG or SVG file, relative to the plugin directory
+    Icon(String),
+    /// small round lamp in the upper right corner
+    Lamp(String),
+    Text {
+        text: String,
+        color: String,
+        size: u32,
+        position: TextPosition,
+    },
+    /// greys out everything below and strikes it through
+    Unavailable,
+}
+
+/// A key image, drawn layer by layer in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyImage {
+    layers: Vec<Layer>,
+}
+
+impl KeyImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn background(mut self, color: &str) -> Self {
+        self.layers.push(Layer::Background(color.to_string()));
+        self
+    }
+
+    pub fn icon(mut self, path: &str) -> Self {
+        self.layers.push(Layer::Icon(path.to_string()));
+        self
+    }
+
+    pub fn lamp(mut self, on: bool) -> Self {
+        let color = if on { COLOR_LAMP_ON } else { COLOR_LAMP_OFF };
+        self.layers.push(Layer::Lamp(color.to_string()));
+        self
+    }
+
+    pub fn text(mut self, text: &str, color: &str, size: u32, position: TextPosition) -> Self {
+        self.layers.push(Layer::Text {
+            text: text.to_string(),
+            color: color.to_string(),
+            size,
+            position,
+        });
+        self
+    }
+
+    /// For features the current bus does not have.
+    pub fn unavailable(mut self) -> Self {
+        self.layers.push(Layer::Unavailable);
+        self
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {size} {size}">"#,
+            size = KEY_SIZE
+        );
+        for layer in &self.layers {
+            svg.push_str(&render_layer(layer));
+        }
+        svg.push_str("</svg>");
+        svg
+    }
+
+    /// The image as `set_image` expects it.
+    pub fn to_data_url(&self) -> String {
+        svg_data_url(&self.to_svg())
+    }
+}
+
+pub fn svg_data_url(svg: &str) -> String {
+    format!("data:image/svg+xml;base64,{}", STANDARD.encode(svg))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+thread_local! {
+    /// Icons already read and encoded, by path. Blinking keys draw the same icons on every
+    /// telemetry tick, the files are only read once.
+    static ICONS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Embeds an icon file, so the image does not depend on where it is shown.
+/// A missing file leaves the layer empty (and is looked for again next time).
+fn icon_data_url(path: &str) -> Option<String> {
+    if let Some(url) = ICONS.with_borrow(|icons| icons.get(path).cloned()) {
+        return Some(url);
+    }
+    let data = fs::read(path).ok()?;
+    let mime = if path.ends_with(".svg") {
+        "image/svg+xml"
+    } else {
+        "image/png"
+    };
+    let url = format!("data:{};base64,{}", mime, STANDARD.encode(data));
+    ICONS.with_borrow_mut(|icons| icons.insert(path.to_string(), url.clone()));
+    Some(url)
+}
+
+fn render_layer(layer: &Layer) -> String {
+    match layer {
+        Layer::Background(color) => format!(
+            r#"<rect width="{size}" height="{size}" fill="{}"/>"#,
+            escape_xml(color),
+            size = KEY_SIZE
+        ),
+        Layer::Icon(path) => match icon_data_url(path) {
+            Some(url) => format!(
+                r#"<image width="{size}" height="{size}" href="{}"/>"#,
+                url,
+                size = KEY_SIZE
+            ),
+            None => "".to_string(),
+        },
+        Layer::Lamp(color) => format!(
+            r##"<circle cx="122" cy="22" r="12" fill="{}" stroke="#000000" stroke-width="3"/>"##,
+            escape_xml(color)
+        ),
+        Layer::Text {
+            text,
+            color,
+            size,
+            position,
+        } => {
+            let y = match position {
+                TextPosition::Top => *size + 8,
+                TextPosition::Center => (KEY_SIZE + *size * 2 / 3) / 2,
+                TextPosition::Bottom => KEY_SIZE - 12,
+            };
+            format!(
+                r#"<text x="{}" y="{}" font-family="sans-serif" font-size="{}" font-weight="bold" fill="{}" text-anchor="middle">{}</text>"#,
+                KEY_SIZE / 2,
+                y,
+                size,
+                escape_xml(color),
+                escape_xml(text)
+            )
+        }
+        Layer::Unavailable => format!(
+            r##"<rect width="{size}" height="{size}" fill="#000000" fill-opacity="0.6"/><line x1="24" y1="24" x2="{end}" y2="{end}" stroke="#ff3b30" stroke-width="8" stroke-linecap="round"/>"##,
+            size = KEY_SIZE,
+            end = KEY_SIZE - 24
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers_in_order() {
+        let svg = KeyImage::new()
+            .background("#112233")
+            .lamp(true)
+            .text("D", COLOR_TEXT, 72, TextPosition::Center)
+            .to_svg();
+        assert_eq!(
+            svg,
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="144" height="144" viewBox="0 0 144 144">"#,
+                r##"<rect width="144" height="144" fill="#112233"/>"##,
+                r##"<circle cx="122" cy="22" r="12" fill="#34c759" stroke="#000000" stroke-width="3"/>"##,
+                r##"<text x="72" y="96" font-family="sans-serif" font-size="72" font-weight="bold" fill="#ffffff" text-anchor="middle">D</text>"##,
+                "</svg>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_text_is_escaped() {
+        let svg = KeyImage::new()
+            .text("<R&D>", COLOR_TEXT, 20, TextPosition::Bottom)
+            .to_svg();
+        assert!(svg.contains(">&lt;R&amp;D&gt;</text>"));
+    }
+
+    #[test]
+    fn test_icon_is_embedded() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/actions/assets/coin.png");
+        let svg = KeyImage::new().icon(path).to_svg();
+        assert!(svg.contains(r#"href="data:image/png;base64,iVBORw0KGgo"#));
+
+        // a missing icon does not break the image
+        let svg = KeyImage::new().icon("does/not/exist.png").to_svg();
+        assert!(!svg.contains("<image"));
+    }
+
+    #[test]
+    fn test_unavailable_is_on_top() {
+        let svg = KeyImage::new().lamp(true).unavailable().to_svg();
+        assert!(svg.ends_with(r##"stroke-linecap="round"/></svg>"##));
+        assert!(svg.find("<circle").unwrap() < svg.find("fill-opacity").unwrap());
+    }
+
+    #[test]
+    fn test_data_url_is_deterministic() {
+        let image = KeyImage::new().background(COLOR_BACKGROUND).lamp(false);
+        assert_eq!(image.to_data_url(), image.clone().to_data_url());
+        assert!(image.to_data_url().starts_with("data:image/svg+xml;base64,PHN2Zy"));
+    }
+}
@@ -0,0 +1,1136 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::de::{DeserializeOwned, Error};
+use serde::{Deserialize, Deserializer};
+
+pub const DEFAULT_TARGET_IP: &str = "127.0.0.1";
+pub const DEFAULT_TARGET_PORT: u16 = 37337;
+pub const DEFAULT_WARNING_THRESHOLD: u8 = 15;
+/// below this speed (km/h) the bus counts as standing for the interlocks
+pub const DEFAULT_INTERLOCK_SPEED: u16 = 3;
+/// how long a key has to be held down for a hold gesture
+pub const DEFAULT_HOLD_TIME: Duration = Duration::from_millis(500);
+/// how long an `until` step of a sequence waits if it has no timeout of its own
+pub const DEFAULT_SEQUENCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses the raw settings of a button (as sent by the property inspector) into a typed struct.
+/// Missing keys fall back to the struct's defaults, unknown values are an error.
+pub fn parse_settings<T: DeserializeOwned>(
+    settings: &HashMap<String, serde_json::Value>,
+) -> Result<T, serde_json::Error> {
+    let value = serde_json::Value::Object(settings.clone().into_iter().collect());
+    serde_json::from_value(value)
+}
+
+/// Same as `parse_settings`, but boxed so the result can be stored in an `ActionInstance`.
+pub fn parse_boxed_settings<T: DeserializeOwned + 'static>(
+    settings: &HashMap<String, serde_json::Value>,
+) -> Result<Box<dyn Any>, serde_json::Error> {
+    parse_settings::<T>(settings).map(|s| Box::new(s) as Box<dyn Any>)
+}
+
+/// Checkboxes in the property inspector send their value ("True") when checked
+/// and nothing at all when unchecked.
+fn checkbox<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(matches!(value.as_deref(), Some("True") | Some("true")))
+}
+
+/// Empty text fields in the property inspector mean "use the default".
+fn target_ip<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if value.trim().is_empty() {
+        Ok(DEFAULT_TARGET_IP.to_string())
+    } else {
+        Ok(value.trim().to_string())
+    }
+}
+
+/// Text fields without a default, surrounding blanks are dropped.
+fn trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(String::deserialize(deserializer)?.trim().to_string())
+}
+
+/// An event name, surrounding blanks are dropped. Empty means none.
+fn event_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let event = String::deserialize(deserializer)?.trim().to_string();
+    if !is_valid_event(&event) {
+        return Err(D::Error::custom(format!("invalid event \"{}\"", event)));
+    }
+    Ok(event)
+}
+
+/// Milliseconds entered as text (or stored as a number), empty means the default hold time.
+fn hold_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Millis {
+        Number(u64),
+        Text(String),
+    }
+
+    match Millis::deserialize(deserializer)? {
+        Millis::Number(ms) => Ok(Duration::from_millis(ms)),
+        Millis::Text(text) if text.trim().is_empty() => Ok(DEFAULT_HOLD_TIME),
+        Millis::Text(text) => text
+            .trim()
+            .trim_end_matches("ms")
+            .trim_end()
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(D::Error::custom),
+    }
+}
+
+/// One step per line, empty lines and lines starting with `#` are skipped.
+fn sequence_steps<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SequenceStep>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+        .map(|(i, line)| {
+            line.parse()
+                .map_err(|e| D::Error::custom(format!("line {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// The port is entered as text, but may also have been stored as a number.
+fn target_port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Port {
+        Number(u16),
+        Text(String),
+    }
+
+    match Port::deserialize(deserializer)? {
+        Port::Number(port) => Ok(port),
+        Port::Text(text) if text.trim().is_empty() => Ok(DEFAULT_TARGET_PORT),
+        Port::Text(text) => text.trim().parse().map_err(D::Error::custom),
+    }
+}
+
+/// A speed in km/h entered as text (or stored as a number), empty means the default.
+fn interlock_speed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Speed {
+        Number(u16),
+        Text(String),
+    }
+
+    match Speed::deserialize(deserializer)? {
+        Speed::Number(speed) => Ok(speed),
+        Speed::Text(text) if text.trim().is_empty() => Ok(DEFAULT_INTERLOCK_SPEED),
+        Speed::Text(text) => text
+            .trim()
+            .trim_end_matches("km/h")
+            .trim_end()
+            .parse()
+            .map_err(D::Error::custom),
+    }
+}
+
+/// A percentage entered as text (or stored as a number), empty means the default.
+fn warning_threshold<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Percent {
+        Number(u8),
+        Text(String),
+    }
+
+    let percent = match Percent::deserialize(deserializer)? {
+        Percent::Number(percent) => percent,
+        Percent::Text(text) if text.trim().is_empty() => DEFAULT_WARNING_THRESHOLD,
+        Percent::Text(text) => text
+            .trim()
+            .trim_end_matches('%')
+            .trim_end()
+            .parse()
+            .map_err(D::Error::custom)?,
+    };
+    if percent > 100 {
+        return Err(D::Error::custom(format!("{}% is more than 100%", percent)));
+    }
+    Ok(percent)
+}
+
+/// Where the telemetry API of the game can be reached.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TargetSettings {
+    #[serde(rename = "TargetIp", deserialize_with = "target_ip")]
+    pub ip: String,
+    #[serde(rename = "TargetPort", deserialize_with = "target_port")]
+    pub port: u16,
+}
+
+impl Default for TargetSettings {
+    fn default() -> Self {
+        TargetSettings {
+            ip: DEFAULT_TARGET_IP.to_string(),
+            port: DEFAULT_TARGET_PORT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum IconTheme {
+    #[default]
+    #[serde(rename = "classic")]
+    Classic,
+    #[serde(rename = "high-contrast")]
+    HighContrast,
+    #[serde(rename = "minimal")]
+    Minimal,
+    /// icons from a folder chosen by the user
+    #[serde(rename = "custom")]
+    Custom,
+}
+
+/// Which icon pack the keys are drawn with, see `theme::Theme`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    #[serde(rename = "IconTheme")]
+    pub theme: IconTheme,
+    #[serde(rename = "IconFolder", deserialize_with = "trimmed")]
+    pub folder: String,
+}
+
+/// Rules checked before a door or gear command is sent, see `interlock::Interlock`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct InterlockSettings {
+    /// doors (and the door clearance) only open while the bus is standing
+    #[serde(rename = "InterlockDoors", deserialize_with = "checkbox")]
+    pub doors: bool,
+    /// doors only open while the parking brake or the bus stop brake is set
+    #[serde(rename = "InterlockDoorBrake", deserialize_with = "checkbox")]
+    pub door_brake: bool,
+    /// only N can be selected while the bus is rolling
+    #[serde(rename = "InterlockGears", deserialize_with = "checkbox")]
+    pub gears: bool,
+    /// km/h, at or below this the bus counts as standing
+    #[serde(rename = "InterlockSpeed", deserialize_with = "interlock_speed")]
+    pub max_speed: u16,
+}
+
+impl Default for InterlockSettings {
+    fn default() -> Self {
+        InterlockSettings {
+            doors: false,
+            door_brake: false,
+            gears: false,
+            max_speed: DEFAULT_INTERLOCK_SPEED,
+        }
+    }
+}
+
+/// Which Stream Deck profile is shown in and out of a bus, see `profiles::get_deck_profile`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ProfileSwitchSettings {
+    #[serde(rename = "SwitchProfiles", deserialize_with = "checkbox")]
+    pub enabled: bool,
+    /// for buses without a Stream Deck profile of their own, empty to stay on the current one
+    #[serde(rename = "BusProfile", deserialize_with = "trimmed")]
+    pub bus: String,
+    /// while the player is not sitting in a bus, empty to stay on the current one
+    #[serde(rename = "DepotProfile", deserialize_with = "trimmed")]
+    pub depot: String,
+}
+
+/// The plugin wide settings, shared by all property inspectors.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GlobalSettings {
+    #[serde(flatten)]
+    pub target: TargetSettings,
+    #[serde(flatten)]
+    pub theme: ThemeSettings,
+    #[serde(flatten)]
+    pub interlock: InterlockSettings,
+    #[serde(flatten)]
+    pub profiles: ProfileSwitchSettings,
+}
+
+/// Settings stored with a single button only contain a target
+/// if they were written by an older property inspector.
+pub fn target_from_settings(
+    settings: &HashMap<String, serde_json::Value>,
+) -> Option<Result<TargetSettings, serde_json::Error>> {
+    if settings.contains_key("TargetIp") || settings.contains_key("TargetPort") {
+        Some(parse_settings::<TargetSettings>(settings))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum DoorSelector {
+    #[default]
+    #[serde(rename = "Door 1")]
+    Door1,
+    #[serde(rename = "Door 2")]
+    Door2,
+    #[serde(rename = "Door 3")]
+    Door3,
+    #[serde(rename = "Door 4")]
+    Door4,
+    Clearance,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DoorSettings {
+    #[serde(rename = "DoorSelector")]
+    pub selector: DoorSelector,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Gear {
+    #[serde(rename = "1")]
+    D,
+    #[default]
+    #[serde(rename = "2")]
+    N,
+    #[serde(rename = "3")]
+    R,
+}
+
+impl Gear {
+    pub fn letter(&self) -> &'static str {
+        match self {
+            Gear::D => "D",
+            Gear::N => "N",
+            Gear::R => "R",
+        }
+    }
+
+    /// value of `VehicleState::gear_selector` when this gear is engaged
+    pub fn state(&self) -> u8 {
+        match self {
+            Gear::D => 1,
+            Gear::N => 2,
+            Gear::R => 3,
+        }
+    }
+
+    /// The gear engaged for a value of `VehicleState::gear_selector`, None if there is none.
+    pub fn from_state(state: u8) -> Option<Gear> {
+        [Gear::D, Gear::N, Gear::R].into_iter().find(|gear| gear.state() == state)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GearSettings {
+    #[serde(rename = "GearSelection")]
+    pub gear: Gear,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum IndicatorSelector {
+    #[default]
+    IndicatorLeft,
+    IndicatorRight,
+    WarningLights,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct IndicatorSettings {
+    #[serde(rename = "IndicatorSelector")]
+    pub selector: IndicatorSelector,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum LightSelector {
+    #[default]
+    MainLight,
+    HighBeam,
+    FogLight,
+    InteriorLight,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LightSettings {
+    #[serde(rename = "LightSelector")]
+    pub selector: LightSelector,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct StopRequestSettings {
+    /// keeps the lamp flashing until the doors are open
+    #[serde(rename = "FlashUntilDoorsOpen", deserialize_with = "checkbox")]
+    pub flash: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Coin {
+    Coins5,
+    Coins10,
+    Coins15,
+    Coins20,
+    Coins30,
+    Coins50,
+    Coins60,
+    Coins100,
+    Coins200,
+    Coins400,
+    Coins600,
+    Coins800,
+    /// an unconfigured key grabs the cash instead of handing out a coin
+    #[default]
+    #[serde(rename = "Take Cash Money")]
+    TakeCashMoney,
+}
+
+impl Coin {
+    /// name of the game event that hands out this coin
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Coin::Coins5 => "Coins5",
+            Coin::Coins10 => "Coins10",
+            Coin::Coins15 => "Coins15",
+            Coin::Coins20 => "Coins20",
+            Coin::Coins30 => "Coins30",
+            Coin::Coins50 => "Coins50",
+            Coin::Coins60 => "Coins60",
+            Coin::Coins100 => "Coins100",
+            Coin::Coins200 => "Coins200",
+            Coin::Coins400 => "Coins400",
+            Coin::Coins600 => "Coins600",
+            Coin::Coins800 => "Coins800",
+            Coin::TakeCashMoney => "Take Cash Money",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ChangeSettings {
+    #[serde(rename = "CashChangeSelect")]
+    pub coin: Coin,
+    #[serde(rename = "AutoLabel", deserialize_with = "checkbox")]
+    pub auto_label: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum PaymentDisplay {
+    #[default]
+    Ticket,
+    PayMethod,
+    PayMethodIcon,
+    Price,
+    Paid,
+    Change,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PaymentSettings {
+    #[serde(rename = "DisplayType")]
+    pub display: PaymentDisplay,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum CustomType {
+    /// `CustomData` is the name of a game event, pressed and released with the key
+    #[default]
+    Event,
+    /// `CustomData` is a raw API command like `sendevent?event=Kneeling`
+    Cmd,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CustomSettings {
+    #[serde(rename = "TypeSelection")]
+    pub kind: CustomType,
+    #[serde(rename = "CustomData")]
+    pub data: String,
+}
+
+fn is_valid_event(event: &str) -> bool {
+    event
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '_' || c == '-')
+}
+
+/// An API command is appended to the vehicle URL, so it must not leave it.
+fn is_valid_command(cmd: &str) -> bool {
+    !cmd.starts_with('?')
+        && cmd
+            .chars()
+            .all(|c| (c.is_ascii_graphic() || c == ' ') && c != '/' && c != '#')
+}
+
+impl CustomSettings {
+    /// Rejects data that can not be a valid event or command.
+    /// Empty data is fine here, the button is simply not configured yet.
+    pub fn validate(&self) -> Result<(), String> {
+        let data = self.data.trim();
+        let valid = match self.kind {
+            CustomType::Event => is_valid_event(data),
+            CustomType::Cmd => is_valid_command(data),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid {:?} \"{}\"", self.kind, self.data))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ButtonFunctionType {
+    /// press and release send game events
+    #[default]
+    #[serde(rename = "event")]
+    Event,
+    /// press and release set a cockpit button, `{"button":"GearSwitch", "state":"0"}`
+    #[serde(rename = "button")]
+    Button,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum FeedbackType {
+    #[default]
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "button")]
+    Button,
+    #[serde(rename = "light")]
+    Light,
+}
+
+/// What a custom button of type `Button` does on press or release.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ButtonCommand {
+    pub button: String,
+    #[serde(deserialize_with = "string_or_number")]
+    pub state: String,
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(text) => Ok(text),
+        serde_json::Value::Number(number) => Ok(number.to_string()),
+        other => Err(D::Error::custom(format!("expected text or number, got {}", other))),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CustomButtonSettings {
+    #[serde(rename = "ButtonFunctionType")]
+    pub function: ButtonFunctionType,
+    #[serde(rename = "OnPressAction")]
+    pub on_press: String,
+    #[serde(rename = "OnReleaseAction")]
+    pub on_release: String,
+    #[serde(rename = "ButtonFeedbackType")]
+    pub feedback: FeedbackType,
+    /// name of the button or light in the vehicle telemetry
+    #[serde(rename = "SourceName")]
+    pub source: String,
+    /// state (or light mode) that switches to the true icon, "On" if empty
+    #[serde(rename = "TrueState")]
+    pub true_state: String,
+    #[serde(rename = "DefaultIcon")]
+    pub default_icon: String,
+    #[serde(rename = "TrueIcon")]
+    pub true_icon: String,
+    #[serde(rename = "DefaultLabel")]
+    pub default_label: String,
+    #[serde(rename = "TrueLabel")]
+    pub true_label: String,
+}
+
+impl CustomButtonSettings {
+    /// Parses an `OnPressAction`/`OnReleaseAction` of a `Button` type custom button,
+    /// None if it is empty.
+    pub fn button_command(data: &str) -> Result<Option<ButtonCommand>, serde_json::Error> {
+        if data.trim().is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_str(data).map(Some)
+    }
+
+    /// Checks that press and release can be sent, see `CustomSettings::validate`.
+    pub fn validate(&self) -> Result<(), String> {
+        for data in [&self.on_press, &self.on_release] {
+            match self.function {
+                ButtonFunctionType::Event => CustomSettings {
+                    kind: CustomType::Event,
+                    data: data.clone(),
+                }
+                .validate()?,
+                ButtonFunctionType::Button => {
+                    Self::button_command(data)
+                        .map_err(|e| format!("invalid button \"{}\": {}", data, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum StartOption {
+    #[default]
+    BusName,
+    Stop,
+    Start,
+    /// Tour, line, route and weather: the game's API is not known to report them,
+    /// keys still set to them stay empty
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct StartSettings {
+    #[serde(rename = "StartOption")]
+    pub option: StartOption,
+    /// game event sent on key press, replaces the default event of Start and Stop
+    #[serde(rename = "CustomData")]
+    pub event: String,
+}
+
+impl StartSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        CustomSettings {
+            kind: CustomType::Event,
+            data: self.event.clone(),
+        }
+        .validate()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum GaugeStyle {
+    #[default]
+    Needle,
+    Digital,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum SpeedUnit {
+    #[default]
+    #[serde(rename = "kmh")]
+    Kmh,
+    #[serde(rename = "mph")]
+    Mph,
+}
+
+impl SpeedUnit {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SpeedUnit::Kmh => "km/h",
+            SpeedUnit::Mph => "mph",
+        }
+    }
+
+    /// converts a speed in km/h (as the game reports it) into this unit
+    pub fn convert_kmh(&self, kmh: f64) -> f64 {
+        match self {
+            SpeedUnit::Kmh => kmh,
+            SpeedUnit::Mph => kmh / 1.609344,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SpeedometerSettings {
+    #[serde(rename = "DisplayStyle")]
+    pub style: GaugeStyle,
+    #[serde(rename = "SpeedUnit")]
+    pub unit: SpeedUnit,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FuelSettings {
+    /// below this level (in percent) the gauge flashes red
+    #[serde(rename = "WarningThreshold", deserialize_with = "warning_threshold")]
+    pub warning_threshold: u8,
+}
+
+impl Default for FuelSettings {
+    fn default() -> Self {
+        FuelSettings {
+            warning_threshold: DEFAULT_WARNING_THRESHOLD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum DialFunction {
+    #[default]
+    Retarder,
+    Wiper,
+    Climate,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DialSettings {
+    #[serde(rename = "DialFunction")]
+    pub function: DialFunction,
+}
+
+/// What an `until` step of a sequence waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCondition {
+    Ignition(bool),
+    Engine(bool),
+    FixingBrake(bool),
+    Gear(Gear),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceStep {
+    /// `event MotorStartStop`
+    Event(String),
+    /// `press MotorStartStop`, held until a `release` step
+    Press(String),
+    Release(String),
+    /// `cmd setbutton?button=Kneeling&state=1`
+    Cmd(String),
+    /// `wait 500`, in milliseconds
+    Wait(Duration),
+    /// `until engine 10000` or `until !fixingbrake`, the sequence is aborted if the
+    /// condition is not met within the timeout (in milliseconds)
+    Until(SequenceCondition, Duration),
+}
+
+impl FromStr for SequenceStep {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        match keyword.to_lowercase().as_str() {
+            "event" | "press" | "release" if rest.is_empty() || !is_valid_event(rest) => {
+                Err(format!("invalid event \"{}\"", rest))
+            }
+            "event" => Ok(SequenceStep::Event(rest.to_string())),
+            "press" => Ok(SequenceStep::Press(rest.to_string())),
+            "release" => Ok(SequenceStep::Release(rest.to_string())),
+            "cmd" if rest.is_empty() || !is_valid_command(rest) => {
+                Err(format!("invalid command \"{}\"", rest))
+            }
+            "cmd" => Ok(SequenceStep::Cmd(rest.to_string())),
+            "wait" => rest
+                .parse()
+                .map(|ms| SequenceStep::Wait(Duration::from_millis(ms)))
+                .map_err(|_| format!("invalid delay \"{}\"", rest)),
+            "until" => parse_until(rest),
+            _ => Err(format!("unknown step \"{}\"", keyword)),
+        }
+    }
+}
+
+fn parse_until(rest: &str) -> Result<SequenceStep, String> {
+    let mut words: Vec<&str> = rest.split_whitespace().collect();
+    let timeout = match words.last().and_then(|w| w.parse().ok()) {
+        Some(ms) => {
+            words.pop();
+            Duration::from_millis(ms)
+        }
+        None => DEFAULT_SEQUENCE_TIMEOUT,
+    };
+
+    let condition = match words.as_slice() {
+        [word, letter] if word.eq_ignore_ascii_case("gear") => {
+            let gear = match letter.to_uppercase().as_str() {
+                "D" => Gear::D,
+                "N" => Gear::N,
+                "R" => Gear::R,
+                _ => return Err(format!("unknown gear \"{}\"", letter)),
+            };
+            SequenceCondition::Gear(gear)
+        }
+        [word] => {
+            let (expected, name) = match word.strip_prefix('!') {
+                Some(name) => (false, name),
+                None => (true, *word),
+            };
+            match name.to_lowercase().as_str() {
+                "ignition" => SequenceCondition::Ignition(expected),
+                "engine" => SequenceCondition::Engine(expected),
+                "fixingbrake" => SequenceCondition::FixingBrake(expected),
+                _ => return Err(format!("unknown condition \"{}\"", name)),
+            }
+        }
+        _ => return Err(format!("invalid condition \"{}\"", rest)),
+    };
+    Ok(SequenceStep::Until(condition, timeout))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SequenceSettings {
+    #[serde(rename = "Steps", deserialize_with = "sequence_steps")]
+    pub steps: Vec<SequenceStep>,
+}
+
+/// Events sent when a key is held down or pressed twice, see `gesture::GestureDetector`.
+/// They can be set for the keys of every action, without them the key reacts at once.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct GestureSettings {
+    #[serde(rename = "HoldEvent", deserialize_with = "event_name")]
+    pub hold: String,
+    #[serde(rename = "DoubleTapEvent", deserialize_with = "event_name")]
+    pub double_tap: String,
+    #[serde(rename = "HoldTime", deserialize_with = "hold_time")]
+    pub hold_time: Duration,
+}
+
+impl Default for GestureSettings {
+    fn default() -> Self {
+        GestureSettings {
+            hold: String::new(),
+            double_tap: String::new(),
+            hold_time: DEFAULT_HOLD_TIME,
+        }
+    }
+}
+
+impl GestureSettings {
+    /// true if the key has neither a hold nor a double tap event
+    pub fn is_empty(&self) -> bool {
+        self.hold.is_empty() && self.double_tap.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn raw(pairs: &[(&str, serde_json::Value)]) -> HashMap<String, serde_json::Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let empty = HashMap::new();
+        assert_eq!(parse_settings::<DoorSettings>(&empty).unwrap().selector, DoorSelector::Door1);
+        assert_eq!(parse_settings::<GearSettings>(&empty).unwrap().gear, Gear::N);
+        assert_eq!(
+            parse_settings::<IndicatorSettings>(&empty).unwrap().selector,
+            IndicatorSelector::IndicatorLeft
+        );
+        let change = parse_settings::<ChangeSettings>(&empty).unwrap();
+        assert_eq!(change.coin, Coin::TakeCashMoney);
+        assert!(!change.auto_label);
+        assert_eq!(
+            parse_settings::<PaymentSettings>(&empty).unwrap().display,
+            PaymentDisplay::Ticket
+        );
+        assert_eq!(parse_settings::<DialSettings>(&empty).unwrap().function, DialFunction::Retarder);
+        assert_eq!(parse_settings::<LightSettings>(&empty).unwrap().selector, LightSelector::MainLight);
+        assert!(!parse_settings::<StopRequestSettings>(&empty).unwrap().flash);
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let door = parse_settings::<DoorSettings>(&raw(&[("DoorSelector", json!("Clearance"))]));
+        assert_eq!(door.unwrap().selector, DoorSelector::Clearance);
+
+        let gear = parse_settings::<GearSettings>(&raw(&[("GearSelection", json!("3"))]));
+        assert_eq!(gear.unwrap().gear, Gear::R);
+
+        let change = parse_settings::<ChangeSettings>(&raw(&[
+            ("CashChangeSelect", json!("Take Cash Money")),
+            ("AutoLabel", json!("True")),
+        ]))
+        .unwrap();
+        assert_eq!(change.coin, Coin::TakeCashMoney);
+        assert!(change.auto_label);
+
+        let payment = parse_settings::<PaymentSettings>(&raw(&[("DisplayType", json!("PayMethodIcon"))]));
+        assert_eq!(payment.unwrap().display, PaymentDisplay::PayMethodIcon);
+
+        let dial = parse_settings::<DialSettings>(&raw(&[("DialFunction", json!("Climate"))]));
+        assert_eq!(dial.unwrap().function, DialFunction::Climate);
+
+        let light = parse_settings::<LightSettings>(&raw(&[("LightSelector", json!("FogLight"))]));
+        assert_eq!(light.unwrap().selector, LightSelector::FogLight);
+
+        let stop = parse_settings::<StopRequestSettings>(&raw(&[("FlashUntilDoorsOpen", json!("True"))]));
+        assert!(stop.unwrap().flash);
+    }
+
+    #[test]
+    fn test_parse_unknown_values() {
+        assert!(parse_settings::<DoorSettings>(&raw(&[("DoorSelector", json!("Door 9"))])).is_err());
+        assert!(parse_settings::<GearSettings>(&raw(&[("GearSelection", json!("P"))])).is_err());
+        assert!(
+            parse_settings::<IndicatorSettings>(&raw(&[("IndicatorSelector", json!(""))])).is_err()
+        );
+        assert!(parse_settings::<ChangeSettings>(&raw(&[("CashChangeSelect", json!(5))])).is_err());
+    }
+
+    #[test]
+    fn test_validate_custom() {
+        let custom = |kind, data: &str| CustomSettings {
+            kind,
+            data: data.to_string(),
+        };
+        assert!(custom(CustomType::Event, "").validate().is_ok());
+        assert!(custom(CustomType::Event, "Kneeling").validate().is_ok());
+        assert!(custom(CustomType::Event, "Take Cash Money").validate().is_ok());
+        assert!(custom(CustomType::Event, "Kneeling&event=Ramp").validate().is_err());
+        assert!(custom(CustomType::Cmd, "sendevent?event=Kneeling").validate().is_ok());
+        assert!(custom(CustomType::Cmd, "sendevent?event=Take Cash Money").validate().is_ok());
+        assert!(custom(CustomType::Cmd, "sendevent?event=Ramp#top").validate().is_err());
+        assert!(custom(CustomType::Cmd, "../player").validate().is_err());
+        assert!(custom(CustomType::Cmd, "?event=Ramp").validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_custom_button() {
+        let button = parse_settings::<CustomButtonSettings>(&raw(&[
+            ("ButtonFunctionType", json!("button")),
+            ("OnPressAction", json!("{\"button\":\"GearSwitch\", \"state\":\"0\"}")),
+            ("ButtonFeedbackType", json!("light")),
+            ("SourceName", json!("ButtonLight Door 1")),
+        ]))
+        .unwrap();
+        assert_eq!(button.function, ButtonFunctionType::Button);
+        assert_eq!(button.feedback, FeedbackType::Light);
+        assert!(button.validate().is_ok());
+
+        let command = CustomButtonSettings::button_command("{\"button\":\"Kneeling\", \"state\":1}");
+        assert_eq!(command.unwrap().unwrap().state, "1");
+        assert!(CustomButtonSettings::button_command("").unwrap().is_none());
+
+        let broken = CustomButtonSettings {
+            function: ButtonFunctionType::Button,
+            on_release: "GearSwitch".to_string(),
+            ..CustomButtonSettings::default()
+        };
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_start() {
+        let start = parse_settings::<StartSettings>(&HashMap::new()).unwrap();
+        assert_eq!(start.option, StartOption::BusName);
+
+        let start = parse_settings::<StartSettings>(&raw(&[
+            ("StartOption", json!("RouteIndex")),
+            ("CustomData", json!("")),
+        ]))
+        .unwrap();
+        assert_eq!(start.option, StartOption::Unsupported);
+        assert!(start.validate().is_ok());
+
+        let start = parse_settings::<StartSettings>(&raw(&[("CustomData", json!("Start?x"))]));
+        assert!(start.unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_speedometer() {
+        let speedometer = parse_settings::<SpeedometerSettings>(&HashMap::new()).unwrap();
+        assert_eq!(speedometer.style, GaugeStyle::Needle);
+        assert_eq!(speedometer.unit, SpeedUnit::Kmh);
+
+        let speedometer = parse_settings::<SpeedometerSettings>(&raw(&[
+            ("DisplayStyle", json!("Digital")),
+            ("SpeedUnit", json!("mph")),
+        ]))
+        .unwrap();
+        assert_eq!(speedometer.style, GaugeStyle::Digital);
+        assert_eq!(speedometer.unit.convert_kmh(80.4672).round(), 50.0);
+    }
+
+    #[test]
+    fn test_parse_fuel() {
+        let fuel = parse_settings::<FuelSettings>(&HashMap::new()).unwrap();
+        assert_eq!(fuel.warning_threshold, DEFAULT_WARNING_THRESHOLD);
+
+        let fuel = parse_settings::<FuelSettings>(&raw(&[("WarningThreshold", json!(" 20 %"))]));
+        assert_eq!(fuel.unwrap().warning_threshold, 20);
+        let fuel = parse_settings::<FuelSettings>(&raw(&[("WarningThreshold", json!(5))]));
+        assert_eq!(fuel.unwrap().warning_threshold, 5);
+
+        assert!(parse_settings::<FuelSettings>(&raw(&[("WarningThreshold", json!("150"))])).is_err());
+    }
+
+    #[test]
+    fn test_parse_target() {
+        let empty = HashMap::new();
+        assert_eq!(parse_settings::<GlobalSettings>(&empty).unwrap().target, TargetSettings::default());
+
+        let target = parse_settings::<GlobalSettings>(&raw(&[
+            ("TargetIp", json!(" 192.168.1.20 ")),
+            ("TargetPort", json!("37338")),
+        ]))
+        .unwrap()
+        .target;
+        assert_eq!(target.ip, "192.168.1.20");
+        assert_eq!(target.port, 37338);
+
+        let target =
+            parse_settings::<TargetSettings>(&raw(&[("TargetIp", json!("")), ("TargetPort", json!(""))]));
+        assert_eq!(target.unwrap(), TargetSettings::default());
+
+        let target = parse_settings::<TargetSettings>(&raw(&[("TargetPort", json!(37339))]));
+        assert_eq!(target.unwrap().port, 37339);
+
+        assert!(parse_settings::<TargetSettings>(&raw(&[("TargetPort", json!("port"))])).is_err());
+        assert!(parse_settings::<TargetSettings>(&raw(&[("TargetPort", json!("99999"))])).is_err());
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        let settings = parse_settings::<GlobalSettings>(&HashMap::new()).unwrap();
+        assert_eq!(settings.theme.theme, IconTheme::Classic);
+
+        let settings = parse_settings::<GlobalSettings>(&raw(&[
+            ("TargetIp", json!("127.0.0.1")),
+            ("IconTheme", json!("custom")),
+            ("IconFolder", json!(" C:\\Icons ")),
+        ]))
+        .unwrap();
+        assert_eq!(settings.theme.theme, IconTheme::Custom);
+        assert_eq!(settings.theme.folder, "C:\\Icons");
+
+        assert!(parse_settings::<ThemeSettings>(&raw(&[("IconTheme", json!("Neon"))])).is_err());
+    }
+
+    #[test]
+    fn test_parse_interlock() {
+        let settings = parse_settings::<GlobalSettings>(&HashMap::new()).unwrap();
+        assert_eq!(settings.interlock, InterlockSettings::default());
+
+        let interlock = parse_settings::<GlobalSettings>(&raw(&[
+            ("InterlockDoors", json!("True")),
+            ("InterlockGears", json!("True")),
+            ("InterlockSpeed", json!(" 5 km/h")),
+        ]))
+        .unwrap()
+        .interlock;
+        assert!(interlock.doors);
+        assert!(!interlock.door_brake);
+        assert!(interlock.gears);
+        assert_eq!(interlock.max_speed, 5);
+
+        let speed = |speed| {
+            parse_settings::<InterlockSettings>(&raw(&[("InterlockSpeed", speed)])).map(|s| s.max_speed)
+        };
+        assert_eq!(speed(json!("")).unwrap(), DEFAULT_INTERLOCK_SPEED);
+        assert_eq!(speed(json!(10)).unwrap(), 10);
+        assert!(speed(json!("fast")).is_err());
+    }
+
+    #[test]
+    fn test_parse_gestures() {
+        // the settings of any action, without gestures
+        let gestures = parse_settings::<GestureSettings>(&raw(&[("DoorSelector", json!("Door 1"))])).unwrap();
+        assert!(gestures.is_empty());
+        assert_eq!(gestures.hold_time, DEFAULT_HOLD_TIME);
+
+        let gestures = parse_settings::<GestureSettings>(&raw(&[
+            ("HoldEvent", json!(" ToggleDoorClearance ")),
+            ("HoldTime", json!("800 ms")),
+        ]))
+        .unwrap();
+        assert_eq!(gestures.hold, "ToggleDoorClearance");
+        assert_eq!(gestures.hold_time, Duration::from_millis(800));
+        assert!(!gestures.is_empty());
+
+        assert!(parse_settings::<GestureSettings>(&raw(&[("DoubleTapEvent", json!("a/b"))])).is_err());
+        assert!(parse_settings::<GestureSettings>(&raw(&[("HoldTime", json!("long"))])).is_err());
+    }
+
+    #[test]
+    fn test_parse_profile_switch() {
+        let settings = parse_settings::<GlobalSettings>(&HashMap::new()).unwrap();
+        assert!(!settings.profiles.enabled);
+
+        let profiles = parse_settings::<GlobalSettings>(&raw(&[
+            ("SwitchProfiles", json!("True")),
+            ("DepotProfile", json!(" The Bus Depot ")),
+        ]))
+        .unwrap()
+        .profiles;
+        assert!(profiles.enabled);
+        assert_eq!(profiles.depot, "The Bus Depot");
+        assert_eq!(profiles.bus, "");
+    }
+
+    #[test]
+    fn test_parse_sequence() {
+        let steps = "# cold start\npress MotorStartStop\nwait 500\nrelease MotorStartStop\n\n\
+                     until engine 15000\nevent FixingBrake\nuntil !fixingbrake\nuntil gear d\n\
+                     cmd setbutton?button=Kneeling&state=1";
+        let sequence = parse_settings::<SequenceSettings>(&raw(&[("Steps", json!(steps))])).unwrap();
+        assert_eq!(
+            sequence.steps,
+            vec![
+                SequenceStep::Press("MotorStartStop".to_string()),
+                SequenceStep::Wait(Duration::from_millis(500)),
+                SequenceStep::Release("MotorStartStop".to_string()),
+                SequenceStep::Until(SequenceCondition::Engine(true), Duration::from_secs(15)),
+                SequenceStep::Event("FixingBrake".to_string()),
+                SequenceStep::Until(SequenceCondition::FixingBrake(false), DEFAULT_SEQUENCE_TIMEOUT),
+                SequenceStep::Until(SequenceCondition::Gear(Gear::D), DEFAULT_SEQUENCE_TIMEOUT),
+                SequenceStep::Cmd("setbutton?button=Kneeling&state=1".to_string()),
+            ]
+        );
+
+        assert!(parse_settings::<SequenceSettings>(&HashMap::new()).unwrap().steps.is_empty());
+    }
+
+    #[test]
+    fn test_parse_sequence_errors() {
+        let error = |steps: &str| {
+            parse_settings::<SequenceSettings>(&raw(&[("Steps", json!(steps))]))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("event FixingBrake\nwait soon").contains("line 2: invalid delay"));
+        assert!(error("jump").contains("unknown step"));
+        assert!(error("event").contains("invalid event"));
+        assert!(error("cmd ../config").contains("invalid command"));
+        assert!(error("until gear P").contains("unknown gear"));
+        assert!(error("until doors open").contains("invalid condition"));
+    }
+
+    #[test]
+    fn test_target_from_settings() {
+        assert!(target_from_settings(&raw(&[("DoorSelector", json!("Door 2"))])).is_none());
+
+        let target = target_from_settings(&raw(&[("TargetIp", json!("10.0.0.5"))]));
+        assert_eq!(target.unwrap().unwrap().ip, "10.0.0.5");
+    }
+
+    #[test]
+    fn test_parse_ignores_global_keys() {
+        let door = parse_settings::<DoorSettings>(&raw(&[
+            ("DoorSelector", json!("Door 2")),
+            ("TargetIp", json!("127.0.0.1")),
+        ]));
+        assert_eq!(door.unwrap().selector, DoorSelector::Door2);
+    }
+}
@@ -0,0 +1,142 @@
+//! Typed changes between two consecutive vehicle states.
+//!
+//! The telemetry task publishes them on a broadcast channel, the registry hands them to
+//! `Action::on_state_change` of every visible button. Actions that only care about a
+//! change (and not about every tick) can react there without touching the main loop.
+
+use komsi::vehicle::VehicleState;
+
+use super::settings::{DoorSelector, Gear};
+
+/// How many changes a slow receiver may fall behind before it misses some.
+pub const STATE_CHANGE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateChange {
+    DoorLampChanged { door: DoorSelector, on: bool },
+    /// None while the gear selector is between the gears
+    GearChanged { gear: Option<Gear> },
+    IgnitionChanged { on: bool },
+    EngineChanged { running: bool },
+    FixingBrakeChanged { set: bool },
+    StopBrakeChanged { on: bool },
+    StopRequestChanged { on: bool },
+    IndicatorChanged { left: bool, right: bool },
+    WarningLightsChanged { on: bool },
+    MainLightChanged { on: bool },
+    HighBeamChanged { on: bool },
+}
+
+/// The lamp of a door button, for the clearance whether the doors are released.
+pub fn door_lamp(door: DoorSelector, state: &VehicleState) -> bool {
+    match door {
+        DoorSelector::Door1 => state.lights_front_door,
+        DoorSelector::Door2 => state.lights_second_door,
+        DoorSelector::Door3 => state.lights_third_door,
+        DoorSelector::Door4 => state.lights_fourth_door,
+        DoorSelector::Clearance => state.doors,
+    }
+}
+
+/// Everything that differs from `old` in `new`, in a fixed order.
+pub fn diff_states(old: &VehicleState, new: &VehicleState) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+
+    for door in [
+        DoorSelector::Door1,
+        DoorSelector::Door2,
+        DoorSelector::Door3,
+        DoorSelector::Door4,
+        DoorSelector::Clearance,
+    ] {
+        if door_lamp(door, old) != door_lamp(door, new) {
+            changes.push(StateChange::DoorLampChanged {
+                door,
+                on: door_lamp(door, new),
+            });
+        }
+    }
+    if old.gear_selector != new.gear_selector {
+        changes.push(StateChange::GearChanged {
+            gear: Gear::from_state(new.gear_selector),
+        });
+    }
+    if old.ignition != new.ignition {
+        changes.push(StateChange::IgnitionChanged { on: new.ignition });
+    }
+    if old.engine != new.engine {
+        changes.push(StateChange::EngineChanged { running: new.engine });
+    }
+    if old.fixing_brake != new.fixing_brake {
+        changes.push(StateChange::FixingBrakeChanged { set: new.fixing_brake });
+    }
+    if old.lights_stop_brake != new.lights_stop_brake {
+        changes.push(StateChange::StopBrakeChanged { on: new.lights_stop_brake });
+    }
+    if old.lights_stop_request != new.lights_stop_request {
+        changes.push(StateChange::StopRequestChanged { on: new.lights_stop_request });
+    }
+    // indicator: left:1, right: 2, none/off: 0
+    if old.indicator != new.indicator {
+        changes.push(StateChange::IndicatorChanged {
+            left: new.indicator == 1,
+            right: new.indicator == 2,
+        });
+    }
+    if old.lights_warning != new.lights_warning {
+        changes.push(StateChange::WarningLightsChanged { on: new.lights_warning });
+    }
+    if old.lights_main != new.lights_main {
+        changes.push(StateChange::MainLightChanged { on: new.lights_main });
+    }
+    if old.lights_high_beam != new.lights_high_beam {
+        changes.push(StateChange::HighBeamChanged { on: new.lights_high_beam });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_changes() {
+        let state = VehicleState::default();
+        assert!(diff_states(&state, &state.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_states() {
+        let old = VehicleState {
+            gear_selector: Gear::N.state(),
+            ..VehicleState::default()
+        };
+        let new = VehicleState {
+            lights_second_door: true,
+            gear_selector: Gear::D.state(),
+            ignition: true,
+            indicator: 2,
+            ..old.clone()
+        };
+        assert_eq!(
+            diff_states(&old, &new),
+            vec![
+                StateChange::DoorLampChanged {
+                    door: DoorSelector::Door2,
+                    on: true
+                },
+                StateChange::GearChanged { gear: Some(Gear::D) },
+                StateChange::IgnitionChanged { on: true },
+                StateChange::IndicatorChanged {
+                    left: false,
+                    right: true
+                },
+            ]
+        );
+
+        // and back
+        let changes = diff_states(&new, &old);
+        assert_eq!(changes.len(), 4);
+        assert!(changes.contains(&StateChange::GearChanged { gear: Some(Gear::N) }));
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use komsi::vehicle::VehicleState;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use the_bus_telemetry::api::{get_current_vehicle_name, get_vehicle, RequestConfig};
+use the_bus_telemetry::api2vehicle::get_vehicle_state_from_api;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch, Notify};
+use tokio::time::MissedTickBehavior;
+
+use super::settings::TargetSettings;
+use super::state_diff::{diff_states, StateChange};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const INFO_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// nothing answers on the target, the game is not running (or not reachable)
+    #[default]
+    Unreachable,
+    /// the game is running, but the player is not sitting in a bus
+    NoVehicle,
+    Connected,
+}
+
+/// The ticket sale going on at the driver's desk.
+///
+/// Experimental: the field names are assumed, the game's API does not document them.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PaymentState {
+    #[serde(rename = "TicketName")]
+    pub ticket: String,
+    /// "Cash" or "Card"
+    #[serde(rename = "PaymentMethod")]
+    pub pay_method: String,
+    #[serde(rename = "Price")]
+    pub price: f64,
+    #[serde(rename = "Paid")]
+    pub paid: f64,
+    #[serde(rename = "Change")]
+    pub change: f64,
+}
+
+/// The extra requests the visible keys need, each one is another request on every poll.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InfoRequests {
+    /// a payment status key is visible
+    pub payment: bool,
+}
+
+/// One poll result of the telemetry task.
+#[derive(Clone, Default)]
+pub struct TelemetrySnapshot {
+    /// false if the player is not sitting in a bus (or the game is not running)
+    pub in_bus: bool,
+    pub status: ConnectionStatus,
+    /// how long the last `get_vehicle` call took
+    pub latency: Option<Duration>,
+    pub vehicle_name: String,
+    pub vehicle_model: String,
+    pub vehicle_state: VehicleState,
+    /// state of every cockpit button by name, as reported by the game (e.g. "Door 1" -> "Neutral")
+    pub buttons: HashMap<String, String>,
+    /// mode of every light by name (e.g. "ButtonLight Door 1" -> "Off")
+    pub lights: HashMap<String, String>,
+    /// None if no ticket is being sold right now
+    pub payment: Option<PaymentState>,
+}
+
+/// Points all requests of `config` to the given game instance.
+pub fn apply_target(config: &mut RequestConfig, target: &TargetSettings) {
+    config.ip = target.ip.clone();
+    config.port = target.port;
+}
+
+/// the-bus-telemetry has no request for the ticket sale, so ask for it ourselves.
+/// The path `ticketsale` and the fields of its answer are assumed, they have
+/// not been checked against the game (the mock serves the same assumption, it is no reference).
+/// If the game does not know them, the keys just stay empty.
+async fn get_vehicle_info<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    config: &RequestConfig,
+    info: &str,
+    wanted: bool,
+) -> Option<T> {
+    if !wanted {
+        return None;
+    }
+    let url = format!(
+        "http://{}:{}/vehicles/{}/{}",
+        config.ip, config.port, config.vehicle_name, info
+    );
+    let response = client.get(url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    serde_json::from_str(&response.text().await.ok()?).ok()
+}
+
+/// get_current_vehicle_name() gives no hint why there is no vehicle,
+/// so check if anything is listening on the target at all.
+async fn is_reachable(target: &TargetSettings) -> bool {
+    let connect = TcpStream::connect((target.ip.as_str(), target.port));
+    matches!(tokio::time::timeout(CONNECT_TIMEOUT, connect).await, Ok(Ok(_)))
+}
+
+/// Starts polling the game in its own task, so a slow or hanging request
+/// never blocks the Stream Deck events. Every poll result is published on the returned channel,
+/// what changed in the vehicle state since the previous poll on `changes`. The changes follow
+/// their snapshot, so an action handling one reads the new state from the snapshot as well.
+/// The ticket sale is only asked for if `info` wants it.
+/// When `target` changes or `rediscover` is notified, the task starts over with finding
+/// the current vehicle, without waiting for the next tick.
+pub fn spawn_telemetry_task(
+    target: watch::Receiver<TargetSettings>,
+    rediscover: Arc<Notify>,
+    changes: broadcast::Sender<StateChange>,
+    info: watch::Receiver<InfoRequests>,
+) -> watch::Receiver<TelemetrySnapshot> {
+    let (tx, rx) = watch::channel(TelemetrySnapshot::default());
+    tokio::spawn(poll_telemetry(tx, target, rediscover, changes, info));
+    rx
+}
+
+async fn poll_telemetry(
+    tx: watch::Sender<TelemetrySnapshot>,
+    mut target: watch::Receiver<TargetSettings>,
+    rediscover: Arc<Notify>,
+    changes: broadcast::Sender<StateChange>,
+    info: watch::Receiver<InfoRequests>,
+) {
+    let mut config = RequestConfig::new();
+//    config.debugging = true;
+    let mut current_target = target.borrow_and_update().clone();
+    apply_target(&mut config, &current_target);
+
+    let mut vehicle_name = "".to_string();
+    let mut vehicle_state = VehicleState::default();
+    let mut zaehler = 0;
+    // a hanging request for the extras must not stall the vehicle state
+    let client = reqwest::Client::builder()
+        .timeout(INFO_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    let mut ticker = tokio::time::interval(POLL_INTERVAL);
+    // don't fire a burst of polls after a request took longer than the interval
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            _ = rediscover.notified() => {
+                vehicle_name = "".to_string();
+            },
+            // handled below, the branch is off once the sender is gone
+            Ok(()) = target.changed() => {},
+        }
+
+        if target.has_changed().unwrap_or(false) {
+            current_target = target.borrow_and_update().clone();
+            apply_target(&mut config, &current_target);
+            vehicle_name = "".to_string();
+        }
+
+        if (vehicle_name.is_empty()) || (zaehler > 10) {
+            config.vehicle_name = "Current".to_string();
+            vehicle_name = get_current_vehicle_name(&config).await;
+            zaehler = 0;
+        }
+
+        if vehicle_name.is_empty() {
+            let status = if is_reachable(&current_target).await {
+                ConnectionStatus::NoVehicle
+            } else {
+                ConnectionStatus::Unreachable
+            };
+            let snapshot = TelemetrySnapshot {
+                status,
+                ..TelemetrySnapshot::default()
+            };
+            if tx.send(snapshot).is_err() {
+                // nobody is listening anymore
+                break;
+            }
+            // leaving the bus switches everything off
+            publish_changes(&changes, &vehicle_state, &VehicleState::default());
+            vehicle_state = VehicleState::default();
+            continue;
+        }
+
+        config.vehicle_name = vehicle_name.clone();
+
+        let started = Instant::now();
+        let vehicle_response = get_vehicle(&config).await;
+        let latency = started.elapsed();
+        if vehicle_response.is_err() {
+            // println!("Error getting vehicle data in JSON.");
+            vehicle_name = "".to_string();
+            zaehler = 12;
+            continue;
+        }
+
+        zaehler = zaehler + 1;
+
+        let vehicle = vehicle_response.unwrap();
+        // println!("{:?}", vehicle);
+        if config.vehicle_model != vehicle.vehicle_model {
+            config.vehicle_model = vehicle.vehicle_model.clone();
+        }
+
+        // komsi only knows the well known buttons and lights, custom buttons may ask for any of them
+        let buttons: HashMap<String, String> = vehicle
+            .buttons
+            .iter()
+            .map(|b| (b.name.clone(), b.state.clone()))
+            .collect();
+        let lights: HashMap<String, String> = vehicle
+            .lights
+            .iter()
+            .map(|l| (l.name.clone(), l.mode.clone()))
+            .collect();
+
+        let new_vehicle_state = get_vehicle_state_from_api(vehicle);
+
+        if config.debugging {
+            new_vehicle_state.print();
+        }
+
+        let wanted = *info.borrow();
+        let payment =
+            get_vehicle_info::<PaymentState>(&client, &config, "ticketsale", wanted.payment).await;
+
+        let snapshot = TelemetrySnapshot {
+            in_bus: true,
+            status: ConnectionStatus::Connected,
+            latency: Some(latency),
+            vehicle_name: vehicle_name.clone(),
+            vehicle_model: config.vehicle_model.clone(),
+            vehicle_state: new_vehicle_state.clone(),
+            buttons,
+            lights,
+            payment,
+        };
+        if tx.send(snapshot).is_err() {
+            break;
+        }
+        publish_changes(&changes, &vehicle_state, &new_vehicle_state);
+        vehicle_state = new_vehicle_state;
+    }
+}
+
+fn publish_changes(changes: &broadcast::Sender<StateChange>, old: &VehicleState, new: &VehicleState) {
+    for change in diff_states(old, new) {
+        // no receiver is fine, nobody is interested yet
+        let _ = changes.send(change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_payment() {
+        let payment: PaymentState = serde_json::from_str(
+            r#"{"TicketName":"Single","PaymentMethod":"Cash","Price":2.5,"Paid":5.0,"Change":2.5}"#,
+        )
+        .unwrap();
+        assert_eq!(payment.ticket, "Single");
+        assert_eq!(payment.pay_method, "Cash");
+        assert_eq!(payment.change, 2.5);
+
+        let payment: PaymentState = serde_json::from_str(r#"{"Price":1}"#).unwrap();
+        assert_eq!(payment.price, 1.0);
+        assert!(payment.ticket.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+//! Icon packs for the keys.
+//!
+//! A pack is a folder with icons named like the ones in `actions/assets`, as PNG or SVG.
+//! It does not need to contain every icon: whatever is missing comes from the classic icons.
+
+use std::path::{Path, PathBuf};
+
+use super::settings::{IconTheme, ThemeSettings};
+
+/// The icons shipped with the plugin, every pack falls back to them.
+const CLASSIC_DIR: &str = "actions/assets";
+/// The other packs shipped with the plugin, one folder per pack.
+const THEMES_DIR: &str = "actions/themes";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Theme {
+    /// None for the classic icons
+    dir: Option<PathBuf>,
+}
+
+impl Theme {
+    pub fn from_settings(settings: &ThemeSettings) -> Self {
+        let dir = match settings.theme {
+            IconTheme::Classic => None,
+            IconTheme::HighContrast => Some(Path::new(THEMES_DIR).join("high-contrast")),
+            IconTheme::Minimal => Some(Path::new(THEMES_DIR).join("minimal")),
+            // no folder chosen yet
+            IconTheme::Custom if settings.folder.is_empty() => None,
+            IconTheme::Custom => Some(PathBuf::from(&settings.folder)),
+        };
+        Theme { dir }
+    }
+
+    /// Path of the icon `name`, given as the file name of the classic icon (e.g. "gear_D_off.png").
+    pub fn icon(&self, name: &str) -> String {
+        if let Some(dir) = &self.dir {
+            let svg = Path::new(name).with_extension("svg");
+            for path in [dir.join(name), dir.join(svg)] {
+                if path.is_file() {
+                    return path.to_string_lossy().into_owned();
+                }
+            }
+        }
+        format!("{}/{}", CLASSIC_DIR, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(name: &str) -> Theme {
+        Theme::from_settings(&ThemeSettings {
+            theme: IconTheme::Custom,
+            folder: format!("{}/assets/actions/themes/{}", env!("CARGO_MANIFEST_DIR"), name),
+        })
+    }
+
+    #[test]
+    fn test_classic_icon() {
+        let theme = Theme::default();
+        assert_eq!(theme.icon("gear_D_off.png"), "actions/assets/gear_D_off.png");
+
+        // a custom theme without a folder keeps the classic icons
+        let theme = Theme::from_settings(&ThemeSettings {
+            theme: IconTheme::Custom,
+            folder: "".to_string(),
+        });
+        assert_eq!(theme, Theme::default());
+    }
+
+    #[test]
+    fn test_pack_icon() {
+        let icon = pack("minimal").icon("indicator_left_on.png");
+        assert!(icon.ends_with("themes/minimal/indicator_left_on.svg"));
+    }
+
+    #[test]
+    fn test_missing_icon_falls_back() {
+        assert_eq!(pack("minimal").icon("coin.png"), "actions/assets/coin.png");
+        assert_eq!(pack("does-not-exist").icon("coin.png"), "actions/assets/coin.png");
+    }
+}
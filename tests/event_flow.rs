@@ -0,0 +1,853 @@
+//! Runs the plugin binary against a fake Stream Deck application
+//! and checks what it sends back for the Stream Deck events.
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use serde_json::json;
+use thebus_mock::streamdeck::{FakeStreamDeck, DEVICE, PLUGIN_UUID};
+use thebus_mock::{sample_vehicle, MockTheBus};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const UUID_INBUS: &str = "de.thatzok.thebus.inbus";
+const UUID_GEARSELECT: &str = "de.thatzok.thebus.gearselect";
+const UUID_PASSENGER_DOORS: &str = "de.thatzok.thebus.dooraction";
+const UUID_CHANGE: &str = "de.thatzok.thebus.changeaction";
+const UUID_CONNECTION_STATUS: &str = "de.thatzok.thebus.connectionstatus";
+const UUID_PAYMENT_STATUS: &str = "de.thatzok.thebus.paymentstatus";
+const UUID_CUSTOM: &str = "de.thatzok.thebus.customaction";
+const UUID_CUSTOM_BUTTON: &str = "de.thatzok.thebus.custombutton";
+const UUID_START: &str = "de.thatzok.thebus.startbutton";
+const UUID_SPEEDOMETER: &str = "de.thatzok.thebus.speedometer";
+const UUID_DIAL: &str = "de.thatzok.thebus.dial";
+const UUID_LIGHTS: &str = "de.thatzok.thebus.lights";
+const UUID_STOP_REQUEST: &str = "de.thatzok.thebus.stoprequest";
+const UUID_SEQUENCE: &str = "de.thatzok.thebus.sequence";
+
+/// The running plugin, killed when the test ends.
+struct Plugin {
+    child: Child,
+    dir: PathBuf,
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+/// Starts the plugin the way the Stream Deck software does: from within the plugin folder.
+async fn start_plugin(name: &str) -> (FakeStreamDeck, Plugin) {
+    let mut deck = FakeStreamDeck::start().await.unwrap();
+
+    let dir = std::env::temp_dir().join(format!("thebus-plugin-{}-{}", name, std::process::id()));
+    copy_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"), &dir);
+
+    let child = Command::new(env!("CARGO_BIN_EXE_thebus-streamdeck-plugin"))
+        .args(deck.plugin_args())
+        .current_dir(&dir)
+        .spawn()
+        .unwrap();
+    let plugin = Plugin { child, dir };
+
+    deck.accept(TIMEOUT).await.unwrap();
+    (deck, plugin)
+}
+
+/// Answers the plugin's request for the global settings with the address of `game`.
+async fn point_to(deck: &mut FakeStreamDeck, game: &MockTheBus) {
+    deck.did_receive_global_settings(json!({
+        "TargetIp": "127.0.0.1",
+        "TargetPort": game.port().to_string()
+    }))
+    .await;
+}
+
+#[tokio::test]
+async fn test_requests_global_settings() {
+    let (mut deck, _plugin) = start_plugin("global").await;
+
+    assert!(deck.expect("getGlobalSettings", PLUGIN_UUID, TIMEOUT).await.is_some());
+}
+
+#[tokio::test]
+async fn test_key_presses_reach_the_target() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("target").await;
+    point_to(&mut deck, &game).await;
+
+    deck.will_appear(UUID_GEARSELECT, "gear1", json!({ "GearSelection": "1" }))
+        .await;
+    deck.key_down(UUID_GEARSELECT, "gear1").await;
+    deck.key_up(UUID_GEARSELECT, "gear1").await;
+
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendeventpress?event=SetGearD", "sendeventrelease?event=SetGearD"]
+    );
+}
+
+#[tokio::test]
+async fn test_target_change_moves_requests() {
+    let first = MockTheBus::start().await.unwrap();
+    let second = MockTheBus::start().await.unwrap();
+    second.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("retarget").await;
+    point_to(&mut deck, &first).await;
+    point_to(&mut deck, &second).await;
+
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Clearance" }))
+        .await;
+    deck.key_down(UUID_PASSENGER_DOORS, "door1").await;
+
+    assert_eq!(
+        second.wait_for_commands(1, TIMEOUT).await,
+        vec!["sendeventpress?event=ToggleDoorClearance"]
+    );
+    assert!(first.commands().is_empty());
+}
+
+#[tokio::test]
+async fn test_will_appear_resets_state() {
+    let (mut deck, _plugin) = start_plugin("inbus").await;
+
+    deck.will_appear(UUID_INBUS, "inbus1", json!({})).await;
+
+    let message = deck.expect("setState", "inbus1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["state"], 0);
+}
+
+#[tokio::test]
+async fn test_door_image_follows_settings() {
+    let (mut deck, _plugin) = start_plugin("doors").await;
+
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Door 2" }))
+        .await;
+    let message = deck.expect("setImage", "door1", TIMEOUT).await.unwrap();
+    assert!(message["payload"]["image"].is_string());
+
+    deck.did_receive_settings(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Clearance" }))
+        .await;
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+}
+
+#[tokio::test]
+async fn test_missing_door_is_unavailable() {
+    let game = MockTheBus::start().await.unwrap();
+    let mut vehicle = sample_vehicle();
+    vehicle["VehicleModel"] = json!("VDL Citea LLE");
+    vehicle["Buttons"]
+        .as_array_mut()
+        .unwrap()
+        .retain(|button| button["Name"] != "Door 3");
+    game.set_vehicle(vehicle);
+
+    let (mut deck, _plugin) = start_plugin("missingdoor").await;
+    deck.will_appear(UUID_PASSENGER_DOORS, "door3", json!({ "DoorSelector": "Door 3" }))
+        .await;
+    let available = deck.expect("setImage", "door3", TIMEOUT).await.unwrap();
+
+    // redrawn as soon as the bus is known
+    point_to(&mut deck, &game).await;
+    let unavailable = deck.expect("setImage", "door3", TIMEOUT).await.unwrap();
+    assert_ne!(unavailable["payload"]["image"], available["payload"]["image"]);
+
+    deck.key_down(UUID_PASSENGER_DOORS, "door3").await;
+    deck.key_up(UUID_PASSENGER_DOORS, "door3").await;
+    assert!(deck.expect("showAlert", "door3", TIMEOUT).await.is_some());
+    assert!(game.commands().is_empty());
+}
+
+#[tokio::test]
+async fn test_door_interlock_while_driving() {
+    let game = MockTheBus::start().await.unwrap();
+    let mut vehicle = sample_vehicle();
+    vehicle["Speed"] = json!(50.0);
+    game.set_vehicle(vehicle);
+
+    let (mut deck, _plugin) = start_plugin("interlock").await;
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Door 1" }))
+        .await;
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+    deck.did_receive_global_settings(json!({
+        "TargetIp": "127.0.0.1",
+        "TargetPort": game.port().to_string(),
+        "InterlockDoors": "True"
+    }))
+    .await;
+    // redrawn once the bus is known, so the telemetry has arrived
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+
+    deck.key_down(UUID_PASSENGER_DOORS, "door1").await;
+    deck.key_up(UUID_PASSENGER_DOORS, "door1").await;
+    assert!(deck.expect("showAlert", "door1", TIMEOUT).await.is_some());
+    // only the (harmless) release goes out
+    assert_eq!(
+        game.wait_for_commands(1, TIMEOUT).await,
+        vec!["sendeventrelease?event=DoorFrontOpenClose"]
+    );
+}
+
+#[tokio::test]
+async fn test_door_hold_toggles_clearance() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("gestures").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(
+        UUID_PASSENGER_DOORS,
+        "door1",
+        json!({ "DoorSelector": "Door 1", "HoldEvent": "ToggleDoorClearance", "HoldTime": "300" }),
+    )
+    .await;
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+
+    // a tap is still the door
+    deck.key_down(UUID_PASSENGER_DOORS, "door1").await;
+    deck.key_up(UUID_PASSENGER_DOORS, "door1").await;
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendeventpress?event=DoorFrontOpenClose", "sendeventrelease?event=DoorFrontOpenClose"]
+    );
+
+    deck.key_down(UUID_PASSENGER_DOORS, "door1").await;
+    assert_eq!(
+        game.wait_for_commands(1, TIMEOUT).await,
+        vec!["sendevent?event=ToggleDoorClearance"]
+    );
+    deck.key_up(UUID_PASSENGER_DOORS, "door1").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(game.commands().is_empty());
+}
+
+#[tokio::test]
+async fn test_invalid_settings_show_alert() {
+    let (mut deck, _plugin) = start_plugin("alert").await;
+
+    deck.will_appear(UUID_GEARSELECT, "gear1", json!({ "GearSelection": "P" }))
+        .await;
+
+    assert!(deck.expect("showAlert", "gear1", TIMEOUT).await.is_some());
+}
+
+#[tokio::test]
+async fn test_change_auto_label() {
+    let (mut deck, _plugin) = start_plugin("change").await;
+
+    deck.will_appear(
+        UUID_CHANGE,
+        "coin1",
+        json!({ "CashChangeSelect": "Coins200", "AutoLabel": "True" }),
+    )
+    .await;
+
+    let message = deck.expect("setTitle", "coin1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["title"], "2.00 €");
+}
+
+#[tokio::test]
+async fn test_connection_status() {
+    let game = MockTheBus::start().await.unwrap();
+
+    let (mut deck, _plugin) = start_plugin("connection").await;
+    deck.will_appear(UUID_CONNECTION_STATUS, "status1", json!({})).await;
+    point_to(&mut deck, &game).await;
+
+    // before the global settings arrive the default target may report "API offline"
+    loop {
+        let message = deck.expect("setTitle", "status1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "No bus" {
+            break;
+        }
+    }
+
+    game.set_vehicle(sample_vehicle());
+    deck.key_down(UUID_CONNECTION_STATUS, "status1").await;
+
+    let message = deck.expect("setState", "status1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["state"], 2);
+}
+
+#[tokio::test]
+async fn test_closed_game_is_offline() {
+    let game = MockTheBus::start().await.unwrap();
+
+    let (mut deck, _plugin) = start_plugin("closed").await;
+    deck.will_appear(UUID_CONNECTION_STATUS, "status1", json!({})).await;
+    point_to(&mut deck, &game).await;
+
+    loop {
+        let message = deck.expect("setTitle", "status1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "No bus" {
+            break;
+        }
+    }
+
+    game.close_game().await;
+    loop {
+        let message = deck.expect("setTitle", "status1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "API\noffline" {
+            break;
+        }
+    }
+
+    game.open_game().await.unwrap();
+    loop {
+        let message = deck.expect("setTitle", "status1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "No bus" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_payment_status_shows_change() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+    game.set_payment(json!({ "TicketName": "Single", "PaymentMethod": "Cash", "Price": 2.5, "Paid": 5.0, "Change": 2.5 }));
+
+    let (mut deck, _plugin) = start_plugin("payment").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(UUID_PAYMENT_STATUS, "pay1", json!({ "DisplayType": "Change" })).await;
+
+    loop {
+        let message = deck.expect("setTitle", "pay1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "2.50 €" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ticket_sale_only_polled_for_payment_keys() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+    game.set_payment(json!({ "Price": 2.5 }));
+
+    let (mut deck, _plugin) = start_plugin("extras").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(UUID_GEARSELECT, "gear1", json!({ "GearSelection": "1" }))
+        .await;
+
+    // a few polls of the vehicle
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let requests = game.requests();
+    assert!(requests.iter().any(|r| r.starts_with("/vehicles/")));
+    assert!(!requests.iter().any(|r| r.ends_with("/ticketsale")));
+
+    deck.will_appear(UUID_PAYMENT_STATUS, "pay1", json!({ "DisplayType": "Price" })).await;
+    loop {
+        let message = deck.expect("setTitle", "pay1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "2.50 €" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_custom_action() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("custom").await;
+    point_to(&mut deck, &game).await;
+
+    deck.will_appear(UUID_CUSTOM, "event1", json!({ "TypeSelection": "Event", "CustomData": "Kneeling" }))
+        .await;
+    deck.will_appear(UUID_CUSTOM, "cmd1", json!({ "TypeSelection": "Cmd", "CustomData": "sendevent?event=Ramp" }))
+        .await;
+    deck.key_down(UUID_CUSTOM, "event1").await;
+    deck.key_up(UUID_CUSTOM, "event1").await;
+    deck.key_down(UUID_CUSTOM, "cmd1").await;
+    deck.key_up(UUID_CUSTOM, "cmd1").await;
+
+    assert_eq!(
+        game.wait_for_commands(3, TIMEOUT).await,
+        vec![
+            "sendeventpress?event=Kneeling",
+            "sendeventrelease?event=Kneeling",
+            "sendevent?event=Ramp"
+        ]
+    );
+
+    deck.will_appear(UUID_CUSTOM, "bad1", json!({ "TypeSelection": "Cmd", "CustomData": "../player" }))
+        .await;
+    assert!(deck.expect("showAlert", "bad1", TIMEOUT).await.is_some());
+}
+
+#[tokio::test]
+async fn test_custom_button_feedback() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("custombutton").await;
+    point_to(&mut deck, &game).await;
+
+    deck.will_appear(
+        UUID_CUSTOM_BUTTON,
+        "btn1",
+        json!({
+            "ButtonFunctionType": "button",
+            "OnPressAction": "{\"button\":\"Kneeling\", \"state\":\"1\"}",
+            "ButtonFeedbackType": "button",
+            "SourceName": "Door Clearance",
+            "TrueState": "Off"
+        }),
+    )
+    .await;
+
+    // the sample vehicle has the door clearance switched off
+    loop {
+        let message = deck.expect("setState", "btn1", TIMEOUT).await.unwrap();
+        if message["payload"]["state"] == 1 {
+            break;
+        }
+    }
+
+    deck.key_down(UUID_CUSTOM_BUTTON, "btn1").await;
+    deck.key_up(UUID_CUSTOM_BUTTON, "btn1").await;
+    assert_eq!(
+        game.wait_for_commands(1, TIMEOUT).await,
+        vec!["setbutton?button=Kneeling&state=1"]
+    );
+}
+
+#[tokio::test]
+async fn test_custom_button_label_resets() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("custombuttonlabel").await;
+    point_to(&mut deck, &game).await;
+
+    deck.will_appear(
+        UUID_CUSTOM_BUTTON,
+        "btn1",
+        json!({
+            "ButtonFeedbackType": "button",
+            "SourceName": "Door Clearance",
+            "TrueState": "Off",
+            "TrueLabel": "Closed"
+        }),
+    )
+    .await;
+
+    loop {
+        let message = deck.expect("setTitle", "btn1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "Closed" {
+            break;
+        }
+    }
+
+    // back in state 0 without a label of its own, the label of state 1 must go
+    let mut vehicle = sample_vehicle();
+    for button in vehicle["Buttons"].as_array_mut().unwrap() {
+        if button["Name"] == "Door Clearance" {
+            button["State"] = json!("On");
+        }
+    }
+    game.set_vehicle(vehicle);
+    let message = deck.expect("setState", "btn1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["state"], 0);
+    let message = deck.expect("setTitle", "btn1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["title"], "");
+}
+
+#[tokio::test]
+async fn test_start_shows_bus() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("start").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(UUID_START, "bus1", json!({ "StartOption": "BusName" })).await;
+    deck.will_appear(UUID_START, "stop1", json!({ "StartOption": "Stop" })).await;
+
+    loop {
+        let message = deck.expect("setTitle", "bus1", TIMEOUT).await.unwrap();
+        if message["payload"]["title"] == "MAN Lion's City 12m" {
+            break;
+        }
+    }
+
+    deck.key_down(UUID_START, "stop1").await;
+    assert_eq!(
+        game.wait_for_commands(1, TIMEOUT).await,
+        vec!["sendevent?event=EndService"]
+    );
+}
+
+#[tokio::test]
+async fn test_speedometer_renders_once_per_value() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("speedometer").await;
+    deck.will_appear(UUID_SPEEDOMETER, "speed1", json!({ "DisplayStyle": "Digital" })).await;
+    let message = deck.expect("setImage", "speed1", TIMEOUT).await.unwrap();
+    assert!(message["payload"]["image"].as_str().unwrap().starts_with("data:image/svg+xml"));
+
+    point_to(&mut deck, &game).await;
+
+    // the sample vehicle stands still, so nothing changes after the first image
+    let messages = deck.drain(Duration::from_secs(2)).await;
+    let images = messages
+        .iter()
+        .filter(|m| m["event"] == "setImage" && m["context"] == "speed1")
+        .count();
+    assert!(images <= 1);
+}
+
+#[tokio::test]
+async fn test_dial_steps_the_retarder() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("dial").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear_dial(UUID_DIAL, "dial1", json!({ "DialFunction": "Retarder" })).await;
+    let message = deck.expect("setFeedback", "dial1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["value"], "0 / 5");
+
+    deck.dial_rotate(UUID_DIAL, "dial1", 2).await;
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendevent?event=RetarderUp"; 2]
+    );
+    let message = deck.expect("setFeedback", "dial1", TIMEOUT).await.unwrap();
+    assert_eq!(message["payload"]["value"], "2 / 5");
+
+    deck.dial_press(UUID_DIAL, "dial1", true).await;
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendevent?event=RetarderDown"; 2]
+    );
+}
+
+#[tokio::test]
+async fn test_dial_counts_sent_steps() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("dialcount").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear_dial(UUID_DIAL, "dial1", json!({ "DialFunction": "Retarder" })).await;
+    deck.dial_rotate(UUID_DIAL, "dial1", 2).await;
+    game.wait_for_commands(2, TIMEOUT).await;
+    expect_dial_value(&mut deck, "2 / 5").await;
+
+    // steps that did not reach the game are not counted
+    game.close_game().await;
+    deck.dial_rotate(UUID_DIAL, "dial1", 1).await;
+    assert!(deck.expect("showAlert", "dial1", TIMEOUT).await.is_some());
+    expect_dial_value(&mut deck, "2 / 5").await;
+    game.open_game().await.unwrap();
+    deck.dial_press(UUID_DIAL, "dial1", true).await;
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendevent?event=RetarderDown"; 2]
+    );
+    expect_dial_value(&mut deck, "0 / 5").await;
+
+    // a dial that comes back starts at rest, nothing is sent for it
+    deck.dial_rotate(UUID_DIAL, "dial1", 1).await;
+    game.wait_for_commands(1, TIMEOUT).await;
+    expect_dial_value(&mut deck, "1 / 5").await;
+    deck.will_disappear(UUID_DIAL, "dial1").await;
+    deck.will_appear_dial(UUID_DIAL, "dial1", json!({ "DialFunction": "Retarder" })).await;
+    expect_dial_value(&mut deck, "0 / 5").await;
+    assert!(game.commands().is_empty());
+}
+
+async fn expect_dial_value(deck: &mut FakeStreamDeck, value: &str) {
+    loop {
+        let message = deck.expect("setFeedback", "dial1", TIMEOUT).await.unwrap();
+        if message["payload"]["value"] == value {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_fog_light_lamp() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("lights").await;
+    deck.will_appear(UUID_LIGHTS, "fog1", json!({ "LightSelector": "FogLight" })).await;
+    let off = deck.expect("setImage", "fog1", TIMEOUT).await.unwrap();
+
+    let mut vehicle = sample_vehicle();
+    for light in vehicle["Lights"].as_array_mut().unwrap() {
+        if light["Name"] == "Fog Light" {
+            light["Mode"] = json!("On");
+        }
+    }
+    game.set_vehicle(vehicle);
+    point_to(&mut deck, &game).await;
+
+    let on = deck.expect("setImage", "fog1", TIMEOUT).await.unwrap();
+    assert_ne!(on["payload"]["image"], off["payload"]["image"]);
+
+    deck.key_down(UUID_LIGHTS, "fog1").await;
+    deck.key_up(UUID_LIGHTS, "fog1").await;
+    assert_eq!(
+        game.wait_for_commands(2, TIMEOUT).await,
+        vec!["sendeventpress?event=ToggleFogLight", "sendeventrelease?event=ToggleFogLight"]
+    );
+}
+
+#[tokio::test]
+async fn test_lamps_follow_the_state_changes() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("statechanges").await;
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Door 1" }))
+        .await;
+    let door_off = deck.expect("setImage", "door1", TIMEOUT).await.unwrap();
+    deck.will_appear(UUID_GEARSELECT, "gearD", json!({ "GearSelection": "1" })).await;
+    let gear_off = deck.expect("setImage", "gearD", TIMEOUT).await.unwrap();
+    deck.will_appear(UUID_GEARSELECT, "gearN", json!({ "GearSelection": "2" })).await;
+    assert!(deck.expect("setImage", "gearN", TIMEOUT).await.is_some());
+    point_to(&mut deck, &game).await;
+    // the sample bus stands in N
+    assert!(deck.expect("setImage", "gearN", TIMEOUT).await.is_some());
+
+    let mut vehicle = sample_vehicle();
+    vehicle["GearSelectorPosition"] = json!(1);
+    for light in vehicle["Lights"].as_array_mut().unwrap() {
+        if light["Name"] == "ButtonLight Door 1" {
+            light["Mode"] = json!("On");
+        }
+    }
+    game.set_vehicle(vehicle);
+    let door_on = deck.expect("setImage", "door1", TIMEOUT).await.unwrap();
+    assert_ne!(door_on["payload"]["image"], door_off["payload"]["image"]);
+    let gear_on = deck.expect("setImage", "gearD", TIMEOUT).await.unwrap();
+    assert_ne!(gear_on["payload"]["image"], gear_off["payload"]["image"]);
+    assert!(deck.expect("setImage", "gearN", TIMEOUT).await.is_some());
+
+    // a key that appears later starts with the current lamp, without waiting for a change
+    deck.will_disappear(UUID_PASSENGER_DOORS, "door1").await;
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Door 1" }))
+        .await;
+    let again = deck.expect("setImage", "door1", TIMEOUT).await.unwrap();
+    assert_eq!(again["payload"]["image"], door_on["payload"]["image"]);
+}
+
+#[tokio::test]
+async fn test_stop_request_reset() {
+    let game = MockTheBus::start().await.unwrap();
+    let mut vehicle = sample_vehicle();
+    for light in vehicle["Lights"].as_array_mut().unwrap() {
+        if light["Name"] == "LED StopRequest" {
+            light["Mode"] = json!("On");
+        }
+    }
+    game.set_vehicle(vehicle);
+
+    let (mut deck, _plugin) = start_plugin("stoprequest").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(UUID_STOP_REQUEST, "stop1", json!({})).await;
+
+    loop {
+        let message = deck.expect("setState", "stop1", TIMEOUT).await.unwrap();
+        if message["payload"]["state"] == 1 {
+            break;
+        }
+    }
+
+    deck.key_down(UUID_STOP_REQUEST, "stop1").await;
+    assert_eq!(
+        game.wait_for_commands(1, TIMEOUT).await,
+        vec!["sendevent?event=ResetStopRequest"]
+    );
+}
+
+#[tokio::test]
+async fn test_sequence_waits_for_the_bus() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("sequence").await;
+    point_to(&mut deck, &game).await;
+    // the engine of the sample vehicle is running, its fixing brake is released
+    deck.will_appear(
+        UUID_SEQUENCE,
+        "seq1",
+        json!({ "Steps": "press MotorStartStop\nwait 100\nrelease MotorStartStop\nuntil engine 2000\nevent FixingBrake" }),
+    )
+    .await;
+    deck.will_appear(
+        UUID_SEQUENCE,
+        "seq2",
+        json!({ "Steps": "event HazardLights\nuntil fixingbrake 500\nevent SetGearD" }),
+    )
+    .await;
+
+    deck.key_down(UUID_SEQUENCE, "seq1").await;
+    assert_eq!(
+        game.wait_for_commands(3, TIMEOUT).await,
+        vec![
+            "sendeventpress?event=MotorStartStop",
+            "sendeventrelease?event=MotorStartStop",
+            "sendevent?event=FixingBrake"
+        ]
+    );
+
+    deck.key_down(UUID_SEQUENCE, "seq2").await;
+    assert!(deck.expect("showAlert", "seq2", TIMEOUT).await.is_some());
+    assert_eq!(game.commands(), vec!["sendevent?event=HazardLights"]);
+}
+
+#[tokio::test]
+async fn test_hold_interlock_while_driving() {
+    let game = MockTheBus::start().await.unwrap();
+    let mut vehicle = sample_vehicle();
+    vehicle["Speed"] = json!(50.0);
+    game.set_vehicle(vehicle);
+
+    let (mut deck, _plugin) = start_plugin("holdinterlock").await;
+    deck.will_appear(
+        UUID_PASSENGER_DOORS,
+        "door1",
+        json!({ "DoorSelector": "Door 1", "HoldEvent": "ToggleDoorClearance", "HoldTime": "300" }),
+    )
+    .await;
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+    deck.did_receive_global_settings(json!({
+        "TargetIp": "127.0.0.1",
+        "TargetPort": game.port().to_string(),
+        "InterlockDoors": "True"
+    }))
+    .await;
+    // redrawn once the bus is known, so the telemetry has arrived
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+
+    deck.key_down(UUID_PASSENGER_DOORS, "door1").await;
+    assert!(deck.expect("showAlert", "door1", TIMEOUT).await.is_some());
+    deck.key_up(UUID_PASSENGER_DOORS, "door1").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(game.commands().is_empty());
+}
+
+#[tokio::test]
+async fn test_sequence_interlock_while_driving() {
+    let game = MockTheBus::start().await.unwrap();
+    let mut vehicle = sample_vehicle();
+    vehicle["Speed"] = json!(50.0);
+    game.set_vehicle(vehicle);
+
+    let (mut deck, _plugin) = start_plugin("sequenceinterlock").await;
+    deck.will_appear(UUID_PASSENGER_DOORS, "door1", json!({ "DoorSelector": "Door 1" }))
+        .await;
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+    deck.will_appear(
+        UUID_SEQUENCE,
+        "seq1",
+        json!({ "Steps": "event Kneeling\npress SetGearR\nrelease SetGearR" }),
+    )
+    .await;
+    deck.did_receive_global_settings(json!({
+        "TargetIp": "127.0.0.1",
+        "TargetPort": game.port().to_string(),
+        "InterlockGears": "True"
+    }))
+    .await;
+    // redrawn once the bus is known, so the telemetry has arrived
+    assert!(deck.expect("setImage", "door1", TIMEOUT).await.is_some());
+
+    deck.key_down(UUID_SEQUENCE, "seq1").await;
+    assert!(deck.expect("showAlert", "seq1", TIMEOUT).await.is_some());
+    assert_eq!(game.commands(), vec!["sendevent?event=Kneeling"]);
+}
+
+#[tokio::test]
+async fn test_sequence_stops_when_its_key_disappears() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("sequencegone").await;
+    point_to(&mut deck, &game).await;
+    deck.will_appear(UUID_SEQUENCE, "seq1", json!({ "Steps": "wait 500\nevent Kneeling" }))
+        .await;
+
+    deck.key_down(UUID_SEQUENCE, "seq1").await;
+    deck.will_disappear(UUID_SEQUENCE, "seq1").await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(game.commands().is_empty());
+}
+
+/// Waits for the plugin to switch the Stream Deck to `profile`.
+async fn expect_profile(deck: &mut FakeStreamDeck, profile: &str) {
+    loop {
+        let message = deck.expect("switchToProfile", PLUGIN_UUID, TIMEOUT).await.unwrap();
+        assert_eq!(message["device"], DEVICE);
+        if message["payload"]["profile"] == profile {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_profile_follows_the_driver() {
+    let game = MockTheBus::start().await.unwrap();
+    game.set_vehicle(sample_vehicle());
+
+    let (mut deck, _plugin) = start_plugin("profiles").await;
+    deck.will_appear(UUID_INBUS, "inbus1", json!({})).await;
+    deck.did_receive_global_settings(json!({
+        "TargetIp": "127.0.0.1",
+        "TargetPort": game.port().to_string(),
+        "SwitchProfiles": "True",
+        "BusProfile": "The Bus",
+        "DepotProfile": "The Bus Depot"
+    }))
+    .await;
+
+    // before the new target is polled the player may not seem to sit in a bus;
+    // the Lion's City has a bundled profile of its own
+    expect_profile(&mut deck, "profiles/LionsCity").await;
+
+    game.leave_vehicle();
+    expect_profile(&mut deck, "The Bus Depot").await;
+
+    let mut vehicle = sample_vehicle();
+    vehicle["VehicleModel"] = json!("Some Bus");
+    game.set_vehicle(vehicle);
+    expect_profile(&mut deck, "The Bus").await;
+}
+
+#[tokio::test]
+async fn test_unknown_action_is_ignored() {
+    let (mut deck, _plugin) = start_plugin("unknown").await;
+
+    deck.will_appear("de.thatzok.thebus.doesnotexist", "x1", json!({})).await;
+    deck.key_down("de.thatzok.thebus.doesnotexist", "x1").await;
+    deck.key_up("de.thatzok.thebus.doesnotexist", "x1").await;
+
+    let messages = deck.drain(Duration::from_millis(500)).await;
+    assert!(messages.iter().all(|m| m["context"] != "x1"));
+}